@@ -1,35 +1,153 @@
-use crate::{codec::RowCodec, engine::Engine, error::DbError, schema::SchemaManager, sql::{ast::{Expr, QueryResult, Stmt}, lexer::Lexer, parser::Parser}, storage::enso_data_dir, types::{Column, TableSchema, Value}};
+use std::{collections::BTreeMap, path::{Path, PathBuf}, sync::Arc, time::Duration};
 
+use crate::{batch::WriteBatch, codec::RowCodec, config::EngineConfig, crypto::{self, EncryptionKey}, durability::Durability, engine::Engine, error::DbError, registry::Database, schema::SchemaManager, snapshot::Snapshot, sql::{ast::{Expr, QueryResult, Stmt}, lexer::Lexer, parser::Parser}, storage::{copy_dir, enso_data_dir}, transaction::Transaction, types::{Column, RecoveryReport, TableSchema, Value}};
+
+// -> Session on a database: the selected DB and table, and an open transaction if there is one.
+//    Sessions opening the same database share its engine (see registry.rs), and cloning one
+//    gives another session on the same database, e.g. one per client of a server.
 pub struct Enso {
-    engine: Engine,
+    base: PathBuf,
+    // selected DB, shared with every other session that has it open
+    database: Option<Arc<Database>>,
     pub db: Option<String>,
     pub table: Option<String>,
-    pub schema: SchemaManager,
+    // open transaction
+    tx: Option<Tx>,
+    // serves a client over the network, which mustn't get to write files where the server runs
+    remote: bool,
+}
+
+// -> Writes of an open transaction, buffered until it's committed, and the snapshot it reads through
+struct Tx {
+    batch: WriteBatch,
+    snapshot: Snapshot,
+}
+
+impl Default for Enso {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// -> New session on the same database and table, the transaction of this one isn't carried over
+impl Clone for Enso {
+    fn clone(&self) -> Self {
+        Self {
+            base: self.base.clone(),
+            database: self.database.clone(),
+            db: self.db.clone(),
+            table: self.table.clone(),
+            tx: None,
+            remote: self.remote,
+        }
+    }
 }
 
 impl Enso {
     pub fn new() -> Self {
-        let engine = Engine::new();
-        let schema = SchemaManager::new();
-        return Self { engine, db: None, table: None, schema };
+        Self { base: enso_data_dir(), database: None, db: None, table: None, tx: None, remote: false }
     }
 
     // -> Create new or use existing database
     pub fn open(db: &str) -> Result<Self, DbError> {
-        let engine = Engine::new();
-        let base = enso_data_dir();
-
-        // create db path if it doesn't exist
-        // let path = format!("data/schema/{}", db);
-        let path = base.join("schema").join(db);
-        if !std::path::Path::new(&path).exists() {
-            std::fs::create_dir_all(&path)?;
-        }
+        Self::open_at(enso_data_dir(), db)
+    }
+
+    // -> Create new or use existing database under the given directory,
+    //    engine settings come from the database's `config.json` if it has one
+    pub fn open_at(path: impl AsRef<Path>, db: &str) -> Result<Self, DbError> {
+        let config = EngineConfig::load(Self::db_path(path.as_ref(), db))?;
+        Self::open_with_config(path, db, config)
+    }
+
+    // -> Create new or use existing database under the given directory with explicit engine settings.
+    //    If another session in this process has it open already, this one shares its engine and
+    //    the settings it was opened with.
+    pub fn open_with_config(path: impl AsRef<Path>, db: &str, config: EngineConfig) -> Result<Self, DbError> {
+        let base = path.as_ref().to_path_buf();
+        let database = Database::open(&base, db, config)?;
 
-        let mut schema = SchemaManager::new();
-        schema.load_db(&db)?;
         let db = Some(db.to_string());
-        return Ok(Self { engine, db, table: None, schema });
+        Ok(Self { base, database: Some(database), db, table: None, tx: None, remote: false })
+    }
+
+    // -> Database that lives in memory only, tables and rows are gone once its last session is dropped
+    pub fn in_memory(db: &str) -> Result<Self, DbError> {
+        let database = Database::in_memory(db)?;
+
+        let db = Some(db.to_string());
+        Ok(Self { base: PathBuf::new(), database: Some(database), db, table: None, tx: None, remote: false })
+    }
+
+    // -> The session for a client connecting over the network. Statements that write files on the
+    //    server (BACKUP TO) fail with `DbError::NotAllowed`, the Rust API is unaffected.
+    pub fn remote(mut self) -> Self {
+        self.remote = true;
+        self
+    }
+
+    fn database(&self) -> Result<&Database, DbError> {
+        self.database.as_deref().ok_or(DbError::NoDatabaseSelected)
+    }
+
+    fn engine(&self) -> Result<&Engine, DbError> {
+        Ok(&self.database()?.engine)
+    }
+
+    // -> Directory holding everything (manifest, segments, indexes, schemas) of a database
+    fn db_path(base: &Path, db: &str) -> PathBuf {
+        base.join(db)
+    }
+
+    // -> Directory of the selected DB
+    pub fn db_dir(&self) -> Result<PathBuf, DbError> {
+        let db = self.db.as_ref().ok_or(DbError::NoDatabaseSelected)?;
+        Ok(Self::db_path(&self.base, db))
+    }
+
+    // -> Copy a consistent snapshot of the selected DB (records and table schemas) into dest,
+    //    a directory that doesn't exist yet. Writes carry on while it's copied.
+    pub fn backup(&self, dest: impl AsRef<Path>) -> Result<(), DbError> {
+        self.engine()?.backup(&dest)?;
+
+        let db = self.db.as_ref().ok_or(DbError::NoDatabaseSelected)?;
+        if let Some(schema_dir) = self.database()?.schema.read().unwrap().db_dir(db) {
+            copy_dir(&schema_dir, &dest.as_ref().join("schema"))?;
+        }
+        Ok(())
+    }
+
+    // -> Restore a backup as database db under path, after validating it. db must not exist yet,
+    //    key is needed if the backed up database is encrypted.
+    pub fn restore(backup: impl AsRef<Path>, path: impl AsRef<Path>, db: &str, key: Option<&EncryptionKey>) -> Result<(), DbError> {
+        Engine::restore(backup, Self::db_path(path.as_ref(), db), key)
+    }
+
+    // -> Create database db under path as the selected DB was at `timestamp` (unix seconds),
+    //    see `Engine::restore_to`. Table schemas are copied as they are now, sealed with key if one is given.
+    pub fn restore_to(&self, timestamp: u64, path: impl AsRef<Path>, db: &str, key: Option<&EncryptionKey>) -> Result<(), DbError> {
+        let dest = Self::db_path(path.as_ref(), db);
+        self.engine()?.restore_to(timestamp, &dest, key)?;
+
+        // written out again rather than copied, the new database may have another key
+        let current = self.db.as_ref().ok_or(DbError::NoDatabaseSelected)?;
+        let mut schema = SchemaManager::new(path).with_cipher(crypto::unlock(&dest, key, true)?);
+        schema.load_db(db, false)?;
+        for table in self.database()?.schema.read().unwrap().tables(current) {
+            schema.insert(db, &table.name, table.clone())?;
+        }
+        Ok(())
+    }
+
+    // -> What startup recovery repaired in the selected DB
+    pub fn recovery_report(&self) -> Option<RecoveryReport> {
+        self.engine().ok().map(|engine| engine.recovery_report())
+    }
+
+    // -> Set how writes to the selected DB are persisted, for every session on it
+    pub fn set_durability(&mut self, durability: Durability) -> Result<(), DbError> {
+        self.engine()?.set_durability(durability);
+        Ok(())
     }
 
     // -> Get selected DB name
@@ -37,40 +155,143 @@ impl Enso {
         self.db.as_deref().unwrap_or("no-db")
     }
 
+    // -> Start a transaction on the selected DB. Row writes are buffered until `commit` writes them
+    //    as one atomic batch, or `rollback` drops them. Reads see the database as it was at `begin`
+    //    with the transaction's own writes on top. Table creation isn't part of the transaction.
+    pub fn begin(&mut self) -> Result<(), DbError> {
+        let engine = self.engine()?;
+        engine.check_writable()?;
+        if self.tx.is_some() {
+            return Err(DbError::TransactionInProgress);
+        }
+
+        self.tx = Some(Tx { batch: WriteBatch::new(), snapshot: engine.snapshot() });
+        Ok(())
+    }
+
+    // -> Write the open transaction, returns the number of row writes in it.
+    //    After a crash either all of them are there or none. Fails with `DbError::TransactionConflict`,
+    //    writing nothing, if another write to one of its rows was made since `begin`.
+    //    The transaction is over either way.
+    pub fn commit(&mut self) -> Result<u64, DbError> {
+        let tx = self.tx.take().ok_or(DbError::NoTransaction)?;
+        let writes = tx.batch.len() as u64;
+
+        self.engine()?.write_since(tx.batch, &tx.snapshot)?;
+        Ok(writes)
+    }
+
+    // -> Drop the writes of the open transaction
+    pub fn rollback(&mut self) -> Result<(), DbError> {
+        self.tx.take().ok_or(DbError::NoTransaction)?;
+        Ok(())
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.tx.is_some()
+    }
+
+    // -> Start a transaction, see `begin`. It's rolled back if the handle is dropped before `Transaction::commit`.
+    pub fn transaction(&mut self) -> Result<Transaction<'_>, DbError> {
+        self.begin()?;
+        Ok(Transaction::new(self))
+    }
+
+    // -> Store an encoded row (expiring after ttl seconds if given), buffered while a transaction is open
+    fn put(&mut self, key: String, value: Vec<u8>, ttl: Option<u64>) -> Result<(), DbError> {
+        let engine = &self.database.as_deref().ok_or(DbError::NoDatabaseSelected)?.engine;
+        let ttl = ttl.map(Duration::from_secs);
+        match (&mut self.tx, ttl) {
+            (Some(tx), ttl) => {
+                match ttl {
+                    Some(ttl) => tx.batch.put_with_ttl(key, value, ttl),
+                    None => tx.batch.put(key, value),
+                }
+                Ok(())
+            }
+            (None, Some(ttl)) => engine.set_raw_with_ttl(key, value, ttl),
+            (None, None) => engine.set_raw(key, value),
+        }
+    }
+
+    // -> Delete a row, buffered while a transaction is open
+    fn remove(&mut self, key: String) -> Result<(), DbError> {
+        let engine = &self.database.as_deref().ok_or(DbError::NoDatabaseSelected)?.engine;
+        match &mut self.tx {
+            Some(tx) => {
+                tx.batch.delete(key);
+                Ok(())
+            }
+            None => engine.delete_raw(key),
+        }
+    }
+
+    // -> Value of a row as this session sees it, through the open transaction if there is one
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, DbError> {
+        let Some(tx) = &self.tx else {
+            return self.engine()?.get_raw(key);
+        };
+
+        match tx.batch.records().iter().rfind(|record| record.key == key) {
+            Some(record) => Ok(Some(record.value.clone()).filter(|_| !record.deleted)),
+            None => tx.snapshot.get_raw(key),
+        }
+    }
+
+    // -> Rows whose key starts with prefix as this session sees them, ordered by key
+    fn scan(&self, prefix: &str) -> Result<Vec<(String, Vec<u8>)>, DbError> {
+        let Some(tx) = &self.tx else {
+            return self.engine()?.scan_prefix(prefix);
+        };
+
+        let mut rows: BTreeMap<String, Vec<u8>> = tx.snapshot.scan_prefix(prefix)?.into_iter().collect();
+        for record in tx.batch.records().iter().filter(|record| record.key.starts_with(prefix)) {
+            if record.deleted {
+                rows.remove(&record.key);
+            } else {
+                rows.insert(record.key.clone(), record.value.clone());
+            }
+        }
+        Ok(rows.into_iter().collect())
+    }
+
     // -> Create new table with schema
     pub fn create_table(&mut self, table: &str, schema: (Vec<Column>, usize)) -> Result<(), DbError> {
+        self.create_table_with_ttl(table, schema, None)
+    }
+
+    // -> Create new table whose rows expire ttl (whole seconds) after they're inserted
+    pub fn create_table_with_ttl(&mut self, table: &str, schema: (Vec<Column>, usize), ttl: Option<Duration>) -> Result<(), DbError> {
         let db = self.db.as_ref().ok_or(DbError::NoDatabaseSelected)?;
-        let base = enso_data_dir();
+        let database = self.database()?;
+        database.engine.check_writable()?;
         let (columns, primary_key) = schema;
 
         // validate primary key
         // let primary_key = columns.iter().position(|c| c.name == primary_key).ok_or(DbError::InvalidPrimaryKey)?;
-        let schema = TableSchema { name: table.to_string(), columns, primary_key };
-
-        // store schema in disk
-        // let path = format!("data/schema/{}/{}.json", db, table);
-        let path = base.join("schema").join(db).join(format!("{}.json", table));
-        let json = serde_json::to_string_pretty(&schema)?;
-        std::fs::write(&path, json)?;
+        let ttl = ttl.map(|ttl| ttl.as_secs().max(1));
+        let schema = TableSchema { name: table.to_string(), columns, primary_key, ttl };
 
-        // cache the schema
-        self.schema.insert(&db, &table, schema)?;
+        // store and cache the schema
+        database.schema.write().unwrap().insert(db, table, schema)?;
 
         self.table = Some(table.to_string());
         Ok(())
     }
 
+    // -> Get schema of a table in the selected DB
+    pub fn table_schema(&self, table: &str) -> Result<TableSchema, DbError> {
+        let db = self.db.as_ref().ok_or(DbError::NoDatabaseSelected)?;
+        self.database()?.schema.read().unwrap().get(db, table).cloned()
+    }
+
     // -> Set current/active table
     pub fn use_table(&mut self, table: &str) -> Result<(), DbError> {
         let db = self.db.as_ref().ok_or(DbError::NoDatabaseSelected)?;
-        let base = enso_data_dir();
 
         // check if table schema exists
         // let path = format!("data/schema/{}/{}.json", db, table);
-        let path = base.join("schema").join(db).join(format!("{}.json", table));
-        if !std::path::Path::new(&path).exists() {
-            return Err(DbError::TableNotFound);
-        }
+        self.database()?.schema.read().unwrap().get(db, table)?;
 
         self.table = Some(table.to_string());
         Ok(())
@@ -80,11 +301,10 @@ impl Enso {
     pub fn insert<I, V>(&mut self, row: I) -> Result<(), DbError>
     where I: IntoIterator<Item = V>, V: Into<Value> {
         let table = self.table.as_ref().ok_or(DbError::NoTableSelected)?;
-        let db = self.db.as_ref().ok_or(DbError::NoDatabaseSelected)?;
         let row: Vec<Value> = row.into_iter().map(Into::into).collect();
 
         // get schema
-        let schema = self.schema.get(db, table)?;
+        let schema = self.table_schema(table)?;
         
         if row.len() != schema.columns.len() {
             return Err(DbError::ColumnCountMismatch);
@@ -103,22 +323,20 @@ impl Enso {
         let pk_idx = schema.primary_key;
         let pk_value = &row[pk_idx];
 
-        let key = format!("{}:{}:{}", db, table, pk_value);
+        let key = format!("{}:{}", table, pk_value);
         let val = RowCodec::encode(&row)?;
+        let ttl = schema.ttl;
 
-        self.engine.set_raw(key, val);
-
-        Ok(())
+        self.put(key, val, ttl)
     }
 
     // -> Insert into specified table
     pub fn insert_into<I, V>(&mut self, table: &str, row: I) -> Result<(), DbError>
     where I: IntoIterator<Item = V>, V: Into<Value> {
-        let db = self.db.as_ref().ok_or(DbError::NoDatabaseSelected)?;
         let row: Vec<Value> = row.into_iter().map(Into::into).collect();
 
         // get schema
-        let schema = self.schema.get(db, table)?;
+        let schema = self.table_schema(table)?;
         
         if row.len() != schema.columns.len() {
             return Err(DbError::ColumnCountMismatch);
@@ -137,25 +355,23 @@ impl Enso {
         let pk_idx = schema.primary_key;
         let pk_value = &row[pk_idx];
 
-        let key = format!("{}:{}:{}", db, table, pk_value);
+        let key = format!("{}:{}", table, pk_value);
         let val = RowCodec::encode(&row)?;
+        let ttl = schema.ttl;
 
-        self.engine.set_raw(key, val);
-
-        Ok(())
+        self.put(key, val, ttl)
     }
 
     // -> Select/fetch all rows
     pub fn select_all(&mut self) -> Result<Vec<Vec<Value>>, DbError> {
-        let db = self.db.as_ref().ok_or(DbError::NoDatabaseSelected)?;
         let table = self.table.as_ref().ok_or(DbError::NoTableSelected)?;
-        let schema = self.schema.get(&db, &table)?;
+        let schema = self.table_schema(table)?;
 
-        let prefix = format!("{}:{}:", db, table);
+        let prefix = format!("{}:", table);
         let mut rows = Vec::new();
 
-        for (key, value) in self.engine.scan_prefix(&prefix) {
-            let row = RowCodec::decode(&value, schema)?;
+        for (_, value) in self.scan(&prefix)? {
+            let row = RowCodec::decode(&value, &schema)?;
             rows.push(row);
         }
 
@@ -164,14 +380,13 @@ impl Enso {
 
     // -> Select all rows from specified table
     pub fn select_all_from(&mut self, table: &str) -> Result<Vec<Vec<Value>>, DbError> {
-        let db = self.db.as_ref().ok_or(DbError::NoDatabaseSelected)?;
-        let schema = self.schema.get(&db, &table)?;
+        let schema = self.table_schema(table)?;
 
-        let prefix = format!("{}:{}:", db, table);
+        let prefix = format!("{}:", table);
         let mut rows = Vec::new();
 
-        for (key, value) in self.engine.scan_prefix(&prefix) {
-            let row = RowCodec::decode(&value, schema)?;
+        for (_, value) in self.scan(&prefix)? {
+            let row = RowCodec::decode(&value, &schema)?;
             rows.push(row);
         }
 
@@ -181,16 +396,15 @@ impl Enso {
     // -> Select row by primary key 
     pub fn select_by_pk<V>(&mut self, pk: V) -> Result<Option<Vec<Value>>, DbError>
     where V: Into<Value> {
-        let db = self.db.as_ref().ok_or(DbError::NoDatabaseSelected)?;
         let table = self.table.as_ref().ok_or(DbError::NoTableSelected)?;
-        let schema = self.schema.get(&db, &table)?;
+        let schema = self.table_schema(table)?;
         let pk = pk.into();
 
-        let key = format!("{}:{}:{}", db, table, pk);
+        let key = format!("{}:{}", table, pk);
 
-        match self.engine.get_raw(&key) {
+        match self.get(&key)? {
             Some(bytes) => {
-                let row = RowCodec::decode(&bytes, schema)?;
+                let row = RowCodec::decode(&bytes, &schema)?;
                 Ok(Some(row))
             },
             None => Ok(None),
@@ -200,15 +414,14 @@ impl Enso {
     // -> Select row by primary key from specified table
     pub fn select_by_pk_from<V>(&mut self, table: &str, pk: V) -> Result<Option<Vec<Value>>, DbError>
     where V: Into<Value> {
-        let db = self.db.as_ref().ok_or(DbError::NoDatabaseSelected)?;
-        let schema = self.schema.get(&db, &table)?;
+        let schema = self.table_schema(table)?;
         let pk = pk.into();
 
-        let key = format!("{}:{}:{}", db, table, pk);
+        let key = format!("{}:{}", table, pk);
 
-        match self.engine.get_raw(&key) {
+        match self.get(&key)? {
             Some(bytes) => {
-                let row = RowCodec::decode(&bytes, schema)?;
+                let row = RowCodec::decode(&bytes, &schema)?;
                 Ok(Some(row))
             },
             None => Ok(None),
@@ -218,7 +431,7 @@ impl Enso {
     pub fn select_where(&mut self, table: &str, filter: Option<Expr>) -> Result<Option<Vec<Vec<Value>>>, DbError> {
         if let Some(filter) = filter {
             match filter {
-                Expr::Eq { column, value } => {
+                Expr::Eq { column: _, value } => {
                     let v = value.eval()?;
                     let row = self.select_by_pk_from(table, v)?;
                     if let Some(row) = row {
@@ -238,33 +451,32 @@ impl Enso {
     // -> Delete row by primary key
     pub fn delete_by_pk<V>(&mut self, pk: V) -> Result<(), DbError>
     where V: Into<Value> {
-        let db = self.db.as_ref().ok_or(DbError::NoDatabaseSelected)?;
         let table = self.table.as_ref().ok_or(DbError::NoTableSelected)?;
         let pk = pk.into();
 
-        let key = format!("{}:{}:{}", db, table, pk);
-        self.engine.delete_raw(key);
-
-        Ok(())
+        let key = format!("{}:{}", table, pk);
+        self.remove(key)
     }
 
     // -> Delete row by primary key from specified table
     pub fn delete_by_pk_from<V>(&mut self, table: &str, pk: V) -> Result<(), DbError>
     where V: Into<Value> {
-        let db = self.db.as_ref().ok_or(DbError::NoDatabaseSelected)?;
         let pk = pk.into();
 
-        let key = format!("{}:{}:{}", db, table, pk);
-        self.engine.delete_raw(key);
-
-        Ok(())
+        let key = format!("{}:{}", table, pk);
+        self.remove(key)
     }
 
+    // -> Delete the rows matching filter, returns how many there were
     pub fn delete_where(&mut self, table: &str, filter: Expr) -> Result<u64, DbError> {
         match filter {
-            Expr::Eq { column, value } => {
+            Expr::Eq { column: _, value } => {
                 let v = value.eval()?;
-                self.delete_by_pk_from(table, v)?;
+                let key = format!("{}:{}", table, v);
+                if self.get(&key)?.is_none() {
+                    return Ok(0);
+                }
+                self.remove(key)?;
                 Ok(1)
             },
             _ => Err(DbError::UnsupportedFilter),
@@ -283,8 +495,8 @@ impl Enso {
 
     pub fn execute(&mut self, stmt: Stmt) -> Result<QueryResult, DbError> {
         match stmt {
-            Stmt::CreateTable { table, columns, primary_key } => {
-                self.create_table(&table, (columns, primary_key))?;
+            Stmt::CreateTable { table, columns, primary_key, ttl } => {
+                self.create_table_with_ttl(&table, (columns, primary_key), ttl.map(Duration::from_secs))?;
                 Ok(QueryResult::Affected(0))
             },
 
@@ -311,7 +523,27 @@ impl Enso {
                 Ok(QueryResult::Affected(deleted))
             }
 
-            _ => Err(DbError::UnsupportedStatement),
+            Stmt::Backup { .. } if self.remote => Err(DbError::NotAllowed { statement: "BACKUP".to_string() }),
+
+            Stmt::Backup { path } => {
+                self.backup(path)?;
+                Ok(QueryResult::Affected(0))
+            }
+
+            Stmt::Begin => {
+                self.begin()?;
+                Ok(QueryResult::Affected(0))
+            }
+
+            Stmt::Commit => {
+                let writes = self.commit()?;
+                Ok(QueryResult::Affected(writes))
+            }
+
+            Stmt::Rollback => {
+                self.rollback()?;
+                Ok(QueryResult::Affected(0))
+            }
         }
     }
 }
@@ -319,16 +551,16 @@ impl Enso {
 #[macro_export]
 macro_rules! row {
     ($($val:expr),* $(,)?) => {
-        vec![$(Value::from($val)),*]
+        vec![$($crate::Value::from($val)),*]
     };
 }
 
 #[macro_export]
 macro_rules! col {
     ($name:ident : $dtype:ident) => {
-        Column::new(
+        $crate::Column::new(
         stringify!($name),
-        types::DataType::$dtype
+        $crate::DataType::$dtype
         )
     };
 }
@@ -337,7 +569,7 @@ macro_rules! col {
 macro_rules! cols {
     ($($name:ident : $dtype:ident),* $(,)?) => {
         vec![
-            $(col!($name : $dtype)),*
+            $($crate::col!($name : $dtype)),*
         ]
     };
 }
@@ -350,9 +582,9 @@ macro_rules! schema {
 
         $(
             let idx = columns.len();
-            columns.push(crate::types::Column::new(
+            columns.push($crate::Column::new(
                 stringify!($name),
-                crate::types::DataType::$dtype
+                $crate::DataType::$dtype
             ));
 
             $(
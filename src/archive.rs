@@ -0,0 +1,127 @@
+use std::{collections::BTreeMap, path::{Path, PathBuf}, sync::Arc};
+
+use crate::{compression::Compression, crypto::{seal_file, Cipher}, error::DbError, record::{Location, Record}, storage::{copy_file, load_manifest, write_file_atomic}, types::ArchiveManifest};
+
+// Archive layout under `<db>/archive/`, kept when `EngineConfig::archive` is on:
+// archive.json    archived logs in the order they were written
+// enso-0001.log   a sealed segment of the log backend (hard linked, so compaction deleting it keeps this copy)
+// wal-000001.log  write-ahead log of the LSM backend as it was before a flush cleared it
+// Logs hold every version of every key in write order, so replaying them up to an instant gives the
+// database as it was then (see `Engine::restore_to`).
+
+#[derive(Clone)]
+pub struct Archive {
+    dir: PathBuf,
+    pub manifest: ArchiveManifest,
+    cipher: Option<Arc<Cipher>>,
+}
+
+impl Archive {
+    // -> Open (or start) the archive of the database in base
+    pub fn open(base: &Path, cipher: Option<Arc<Cipher>>) -> Result<Self, DbError> {
+        let dir = base.join("archive");
+        std::fs::create_dir_all(&dir)?;
+
+        let path = dir.join("archive.json");
+        let manifest = if path.exists() {
+            load_manifest(&path, cipher.as_deref())?
+        } else {
+            ArchiveManifest::default()
+        };
+
+        Ok(Self { dir, manifest, cipher })
+    }
+
+    pub fn cipher(&self) -> Option<&Cipher> {
+        self.cipher.as_deref()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.manifest.logs.is_empty()
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        let data = serde_json::to_vec_pretty(&self.manifest)?;
+        let path = self.dir.join("archive.json");
+        write_file_atomic(&path, &seal_file(self.cipher.as_deref(), data, &path))
+    }
+
+    // -> Archive a log that never changes again, linked when the filesystem allows it
+    pub fn add_sealed(&mut self, path: &Path, name: &str) -> std::io::Result<()> {
+        // reopening a database can seal the same segment twice after a crash
+        if self.manifest.logs.last().is_some_and(|last| last == name) {
+            return Ok(());
+        }
+
+        let dest = self.dir.join(name);
+        let _ = std::fs::remove_file(&dest);
+        if std::fs::hard_link(path, &dest).is_err() {
+            copy_file(path, &dest, None)?;
+        }
+        self.manifest.logs.push(name.to_string());
+        self.save()
+    }
+
+    // -> Archive a copy of a log that is about to be rewritten
+    pub fn add_copy(&mut self, path: &Path, name: &str) -> std::io::Result<()> {
+        copy_file(path, &self.dir.join(name), None)?;
+        self.manifest.logs.push(name.to_string());
+        self.save()
+    }
+
+    // -> Archive records as a log of their own
+    pub fn add_records<'a>(&mut self, records: impl IntoIterator<Item = &'a Record>, name: &str) -> std::io::Result<()> {
+        let mut data = Vec::new();
+        for record in records {
+            let at = Location::new(sealed_name(name), data.len() as u64);
+            data.extend_from_slice(&record.encode(Compression::None, self.cipher.as_deref(), at));
+        }
+        write_file_atomic(&self.dir.join(name), &data)?;
+        self.manifest.logs.push(name.to_string());
+        self.save()
+    }
+
+    // -> Replay every archived log, see `replay_log`
+    pub fn replay(&self, until: u64, state: &mut BTreeMap<String, Record>) -> Result<(), DbError> {
+        for name in &self.manifest.logs {
+            replay_log(&self.dir.join(name), None, self.cipher.as_deref(), until, state)?;
+        }
+        Ok(())
+    }
+}
+
+// -> Apply the records of a log (its first len bytes if given) written at or before `until`
+//    to state, tombstones included
+pub fn replay_log(path: &Path, len: Option<u64>, cipher: Option<&Cipher>, until: u64, state: &mut BTreeMap<String, Record>) -> Result<(), DbError> {
+    let mut buf = std::fs::read(path)?;
+    if let Some(len) = len {
+        buf.truncate(len as usize);
+    }
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    replay_records(&buf, &name, cipher, until, state)
+}
+
+// -> Same as `replay_log` for a log already read into memory
+pub fn replay_records(buf: &[u8], name: &str, cipher: Option<&Cipher>, until: u64, state: &mut BTreeMap<String, Record>) -> Result<(), DbError> {
+    let mut pos = 0;
+    while pos < buf.len() {
+        let corruption = || DbError::Corruption { segment: name.to_string(), offset: pos as u64 };
+        let len = Record::record_len(&buf[pos..]).ok_or_else(corruption)?;
+        let at = Location::new(sealed_name(name), pos as u64);
+        let records = buf.get(pos..pos + len).and_then(|b| Record::decode_entry(b, cipher, at)).ok_or_else(corruption)?;
+        pos += len;
+
+        for (_, record) in records.into_iter().filter(|(_, r)| r.timestamp <= until) {
+            state.insert(record.key.clone(), record);
+        }
+    }
+
+    Ok(())
+}
+
+// -> Name the records of an archived log were sealed under, copies of the LSM write-ahead log
+//    keep the name of the log they were written to
+fn sealed_name(name: &str) -> &str {
+    if name.starts_with("wal-") { "wal.log" } else { name }
+}
@@ -0,0 +1,267 @@
+use std::{path::Path, sync::{Arc, Mutex}};
+
+use crate::{config::EngineConfig, crypto::Cipher, durability::Durability, error::DbError, lsm::LsmStorage, record::Record, storage::Storage, types::{CompactionStats, CompressionStats, RecoveryReport}};
+
+// -> Where an engine keeps its records. Implementations do their own locking, so the engine can
+//    share one between writers, readers and a background compaction.
+pub trait StorageBackend: Send + Sync {
+    // -> Append a record (tombstones included), returns its write sequence
+    fn append(&self, record: &Record) -> std::io::Result<u64>;
+
+    // -> Append records atomically: readers and recovery see all of them or none.
+    //    Returns the write sequence of the last one.
+    fn append_batch(&self, records: &[Record]) -> std::io::Result<u64>;
+
+    // -> `append_batch`, unless one of the keys was written after the snapshot at seq (still open):
+    //    then nothing is appended and it fails with `DbError::TransactionConflict`.
+    //    The check and the append happen under one lock.
+    fn append_batch_since(&self, records: &[Record], seq: u64) -> Result<u64, DbError>;
+
+    // -> Live record of a key, None if it was never written or is deleted
+    fn get(&self, key: &str) -> Result<Option<Record>, DbError>;
+
+    // -> Live records whose key starts with prefix, ordered by key
+    fn scan_prefix(&self, prefix: &str) -> Result<Vec<Record>, DbError>;
+
+    // -> Pin the current state for snapshot reads, returns the write sequence it's at.
+    //    Until it's released, writes must not change what `get_at` and `scan_prefix_at` return for it.
+    fn snapshot(&self) -> u64;
+
+    fn release_snapshot(&self, seq: u64);
+
+    // -> Live record of a key as the snapshot at seq sees it
+    fn get_at(&self, key: &str, seq: u64) -> Result<Option<Record>, DbError>;
+
+    // -> Live records whose key starts with prefix as the snapshot at seq sees them, ordered by key
+    fn scan_prefix_at(&self, prefix: &str, seq: u64) -> Result<Vec<Record>, DbError>;
+
+    // -> Make every appended record durable, returns the last write sequence covered.
+    //    Used by group commit, so it shouldn't block appends while syncing.
+    fn sync(&self) -> std::io::Result<u64>;
+
+    fn set_durability(&self, durability: Durability);
+
+    // -> Number of segments (or tables) compaction would merge down
+    fn segment_count(&self) -> usize;
+
+    // -> Merge segments into fewer ones, called from a background thread once
+    //    `segment_count` goes past `EngineConfig::max_segments`
+    fn compact(&self) -> Result<(), DbError>;
+
+    fn recovery_report(&self) -> RecoveryReport {
+        RecoveryReport::default()
+    }
+
+    fn compaction_stats(&self) -> CompactionStats {
+        CompactionStats::default()
+    }
+
+    fn compression_stats(&self) -> CompressionStats {
+        CompressionStats::default()
+    }
+
+    // -> Copy a consistent snapshot of every record into the directory dest while writes carry on
+    fn backup(&self, _dest: &Path) -> Result<(), DbError> {
+        Err(DbError::NotPersistent)
+    }
+
+    // -> Live records as they were at the given time (unix seconds), from archived history
+    fn records_at(&self, _timestamp: u64) -> Result<Vec<Record>, DbError> {
+        Err(DbError::ArchiveDisabled)
+    }
+}
+
+// -> Append-only segment logs with an in-memory keydir, see storage.rs
+pub struct LogBackend {
+    storage: Mutex<Storage>,
+}
+
+impl LogBackend {
+    pub fn open(path: impl AsRef<Path>, config: EngineConfig, cipher: Option<Arc<Cipher>>) -> Result<Self, DbError> {
+        Ok(Self { storage: Mutex::new(Storage::open(path, config, cipher)?) })
+    }
+
+    // -> Check every record of a database directory without opening it
+    pub fn verify(path: &Path, cipher: Option<Arc<Cipher>>) -> Result<(), DbError> {
+        Storage::verify(path, cipher)
+    }
+}
+
+impl StorageBackend for LogBackend {
+    fn append(&self, record: &Record) -> std::io::Result<u64> {
+        let mut storage = self.storage.lock().unwrap();
+        storage.append(record).map(|(_, seq)| seq)
+    }
+
+    fn append_batch(&self, records: &[Record]) -> std::io::Result<u64> {
+        self.storage.lock().unwrap().append_batch(records)
+    }
+
+    fn append_batch_since(&self, records: &[Record], seq: u64) -> Result<u64, DbError> {
+        let mut storage = self.storage.lock().unwrap();
+        if records.iter().any(|record| storage.written_since(&record.key, seq)) {
+            return Err(DbError::TransactionConflict);
+        }
+        Ok(storage.append_batch(records)?)
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Record>, DbError> {
+        Storage::get(&self.storage, key)
+    }
+
+    fn scan_prefix(&self, prefix: &str) -> Result<Vec<Record>, DbError> {
+        self.storage.lock().unwrap().scan_prefix(prefix)
+    }
+
+    fn snapshot(&self) -> u64 {
+        self.storage.lock().unwrap().snapshot()
+    }
+
+    fn release_snapshot(&self, seq: u64) {
+        self.storage.lock().unwrap().release_snapshot(seq)
+    }
+
+    fn get_at(&self, key: &str, seq: u64) -> Result<Option<Record>, DbError> {
+        Storage::get_at(&self.storage, key, seq)
+    }
+
+    fn scan_prefix_at(&self, prefix: &str, seq: u64) -> Result<Vec<Record>, DbError> {
+        Storage::scan_prefix_at(&self.storage, prefix, seq)
+    }
+
+    fn sync(&self) -> std::io::Result<u64> {
+        // sync a handle to the active segment outside the lock
+        let (file, seq) = self.storage.lock().unwrap().sync_handle()?;
+        file.sync_data()?;
+        Ok(seq)
+    }
+
+    fn set_durability(&self, durability: Durability) {
+        self.storage.lock().unwrap().config.durability = durability;
+    }
+
+    fn segment_count(&self) -> usize {
+        self.storage.lock().unwrap().manifest.segments.len()
+    }
+
+    fn compact(&self) -> Result<(), DbError> {
+        Storage::compact(&self.storage)
+    }
+
+    fn backup(&self, dest: &Path) -> Result<(), DbError> {
+        Storage::backup(&self.storage, dest)
+    }
+
+    fn records_at(&self, timestamp: u64) -> Result<Vec<Record>, DbError> {
+        Storage::records_at(&self.storage, timestamp)
+    }
+
+    fn recovery_report(&self) -> RecoveryReport {
+        self.storage.lock().unwrap().recovery.clone()
+    }
+
+    fn compaction_stats(&self) -> CompactionStats {
+        self.storage.lock().unwrap().compaction_stats.clone()
+    }
+
+    fn compression_stats(&self) -> CompressionStats {
+        self.storage.lock().unwrap().compression_stats.clone()
+    }
+}
+
+// -> Memtable and sorted tables, see lsm.rs
+pub struct LsmBackend {
+    storage: Mutex<LsmStorage>,
+}
+
+impl LsmBackend {
+    pub fn open(path: impl AsRef<Path>, config: EngineConfig, cipher: Option<Arc<Cipher>>) -> Result<Self, DbError> {
+        Ok(Self { storage: Mutex::new(LsmStorage::open(path, config, cipher)?) })
+    }
+
+    // -> Check every table and the write-ahead log of a database directory without opening it
+    pub fn verify(path: &Path, cipher: Option<Arc<Cipher>>) -> Result<(), DbError> {
+        LsmStorage::verify(path, cipher)
+    }
+}
+
+impl StorageBackend for LsmBackend {
+    fn append(&self, record: &Record) -> std::io::Result<u64> {
+        self.storage.lock().unwrap().append(record)
+    }
+
+    fn append_batch(&self, records: &[Record]) -> std::io::Result<u64> {
+        self.storage.lock().unwrap().append_batch(records)
+    }
+
+    fn append_batch_since(&self, records: &[Record], seq: u64) -> Result<u64, DbError> {
+        let mut storage = self.storage.lock().unwrap();
+        if records.iter().any(|record| storage.written_since(&record.key, seq)) {
+            return Err(DbError::TransactionConflict);
+        }
+        Ok(storage.append_batch(records)?)
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Record>, DbError> {
+        LsmStorage::get(&self.storage, key)
+    }
+
+    fn scan_prefix(&self, prefix: &str) -> Result<Vec<Record>, DbError> {
+        LsmStorage::scan_prefix(&self.storage, prefix)
+    }
+
+    fn snapshot(&self) -> u64 {
+        self.storage.lock().unwrap().snapshot()
+    }
+
+    fn release_snapshot(&self, seq: u64) {
+        self.storage.lock().unwrap().release_snapshot(seq)
+    }
+
+    fn get_at(&self, key: &str, seq: u64) -> Result<Option<Record>, DbError> {
+        LsmStorage::get_at(&self.storage, key, seq)
+    }
+
+    fn scan_prefix_at(&self, prefix: &str, seq: u64) -> Result<Vec<Record>, DbError> {
+        LsmStorage::scan_prefix_at(&self.storage, prefix, seq)
+    }
+
+    fn sync(&self) -> std::io::Result<u64> {
+        // sync a handle to the write-ahead log outside the lock
+        let (file, seq) = self.storage.lock().unwrap().sync_handle()?;
+        file.sync_data()?;
+        Ok(seq)
+    }
+
+    fn set_durability(&self, durability: Durability) {
+        self.storage.lock().unwrap().config.durability = durability;
+    }
+
+    fn segment_count(&self) -> usize {
+        self.storage.lock().unwrap().table_count()
+    }
+
+    fn compact(&self) -> Result<(), DbError> {
+        LsmStorage::compact(&self.storage)
+    }
+
+    fn backup(&self, dest: &Path) -> Result<(), DbError> {
+        LsmStorage::backup(&self.storage, dest)
+    }
+
+    fn records_at(&self, timestamp: u64) -> Result<Vec<Record>, DbError> {
+        LsmStorage::records_at(&self.storage, timestamp)
+    }
+
+    fn recovery_report(&self) -> RecoveryReport {
+        self.storage.lock().unwrap().recovery.clone()
+    }
+
+    fn compaction_stats(&self) -> CompactionStats {
+        self.storage.lock().unwrap().compaction_stats.clone()
+    }
+
+    fn compression_stats(&self) -> CompressionStats {
+        self.storage.lock().unwrap().compression_stats.clone()
+    }
+}
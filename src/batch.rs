@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use crate::record::Record;
+
+// -> Puts and deletes applied together by `Engine::write`: they land in the log as one entry,
+//    so readers and recovery see either all of them or none. Later operations on a key win.
+#[derive(Default, Clone)]
+pub struct WriteBatch {
+    // timestamps are set when the batch is written
+    records: Vec<Record>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(&mut self, key: String, value: Vec<u8>) {
+        self.records.push(Record::new(key, value, 0, false));
+    }
+
+    // -> Put a value that expires once ttl (whole seconds, at least one) has passed, see `Engine::set_raw_with_ttl`
+    pub fn put_with_ttl(&mut self, key: String, value: Vec<u8>, ttl: Duration) {
+        self.records.push(Record::new(key, value, 0, false).with_ttl(ttl.as_secs().max(1)));
+    }
+
+    pub fn delete(&mut self, key: String) {
+        self.records.push(Record::new(key, Vec::new(), 0, true));
+    }
+
+    // -> Number of operations in the batch
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+
+    // -> Operations in the order they were queued
+    pub(crate) fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    pub(crate) fn into_records(self) -> Vec<Record> {
+        self.records
+    }
+}
@@ -0,0 +1,80 @@
+use std::path::Path;
+
+use crate::{crypto::{open_file, seal_file, Cipher}, storage::write_file_atomic, utils::{decode_u32, encode_u32}};
+
+// Bloom filter file layout (big endian):
+// [hash_count: u32][bits] ... [crc32: u32]
+// The checksum covers everything before it, a filter failing it is rebuilt from its table.
+// In encrypted databases the whole file is sealed (see crypto.rs).
+
+// ~1% false positives
+const BITS_PER_KEY: usize = 10;
+const HASH_COUNT: u32 = 7;
+
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    hash_count: u32,
+}
+
+impl BloomFilter {
+    pub fn with_capacity(keys: usize) -> Self {
+        let bytes = (keys * BITS_PER_KEY).div_ceil(8).max(1);
+        Self { bits: vec![0; bytes], hash_count: HASH_COUNT }
+    }
+
+    pub fn insert(&mut self, key: &str) {
+        let positions: Vec<usize> = self.bit_positions(key).collect();
+        for bit in positions {
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    // -> false means the key was never inserted, true means it probably was
+    pub fn may_contain(&self, key: &str) -> bool {
+        self.bit_positions(key).all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    // double hashing: probe i lands on h1 + i * h2
+    fn bit_positions(&self, key: &str) -> impl Iterator<Item = usize> {
+        let hash = fnv1a(key.as_bytes());
+        let (h1, h2) = (hash as u32, (hash >> 32) as u32 | 1);
+        let len = self.bits.len() * 8;
+        (0..self.hash_count).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) as usize % len)
+    }
+
+    pub fn write(&self, path: &Path, cipher: Option<&Cipher>) -> std::io::Result<()> {
+        let mut buf = Vec::with_capacity(self.bits.len() + 8);
+        buf.extend_from_slice(&encode_u32(self.hash_count));
+        buf.extend_from_slice(&self.bits);
+        let crc = crc32fast::hash(&buf);
+        buf.extend_from_slice(&encode_u32(crc));
+
+        write_file_atomic(path, &seal_file(cipher, buf, path))
+    }
+
+    pub fn load(path: &Path, cipher: Option<&Cipher>) -> std::io::Result<Self> {
+        let buf = open_file(cipher, std::fs::read(path)?, path)?;
+        let invalid = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), msg));
+
+        if buf.len() < 9 {
+            return Err(invalid("truncated bloom filter"));
+        }
+
+        let (body, crc) = buf.split_at(buf.len() - 4);
+        if crc32fast::hash(body) != decode_u32(crc) {
+            return Err(invalid("bloom filter checksum mismatch"));
+        }
+
+        Ok(Self { hash_count: decode_u32(&body[0..4]), bits: body[4..].to_vec() })
+    }
+}
+
+// stable across builds and platforms, unlike std's hasher, since filters are persisted
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+
+const ZSTD_LEVEL: i32 = 3;
+
+// -> Codec applied to record values on write, recorded per record so databases can switch
+//    codecs (or mix compressed and uncompressed records) and still read everything back
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    // fast, moderate ratio
+    Lz4,
+    // slower, better ratio
+    Zstd,
+}
+
+impl Compression {
+    // -> Codec id stored in the record header
+    pub fn id(&self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Lz4 => 1,
+            Compression::Zstd => 2,
+        }
+    }
+
+    // -> Compressed value, None when this codec stores values as is
+    pub fn compress(&self, data: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Compression::None => None,
+            Compression::Lz4 => Some(lz4_flex::block::compress_prepend_size(data)),
+            Compression::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL).ok(),
+        }
+    }
+}
+
+// -> Decode a value stored with the given codec id, None if the id is unknown or the data is invalid
+pub fn decompress(codec: u8, data: &[u8]) -> Option<Vec<u8>> {
+    match codec {
+        0 => Some(data.to_vec()),
+        1 => lz4_flex::block::decompress_size_prepended(data).ok(),
+        2 => zstd::stream::decode_all(data).ok(),
+        _ => None,
+    }
+}
@@ -0,0 +1,165 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{compression::Compression, crypto::EncryptionKey, durability::Durability, error::DbError};
+
+// -> When the engine merges sealed segments
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompactionPolicy {
+    // compact in the background once there are more than `max_segments` segments
+    #[default]
+    Automatic,
+    // only compact when `Engine::compact` is called
+    Manual,
+}
+
+// -> On-disk structure the engine stores data in
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BackendKind {
+    // append-only segment logs with an in-memory hash index of every key
+    #[default]
+    Log,
+    // sorted memtable flushed into immutable sorted tables, cheaper prefix and range scans
+    Lsm,
+}
+
+impl BackendKind {
+    // -> Backend the database in dir was created with, told apart by where its manifest is.
+    //    None if there's no database there yet.
+    pub fn of_database(dir: &Path) -> Option<Self> {
+        if dir.join("manifest.json").exists() {
+            Some(BackendKind::Log)
+        } else if dir.join("lsm").join("manifest.json").exists() {
+            Some(BackendKind::Lsm)
+        } else {
+            None
+        }
+    }
+}
+
+// -> Tunables for an engine, read from `config.json` in the database directory
+//    (missing fields fall back to their defaults) or built in code:
+//
+//    EngineConfig::default().segment_size(64 * 1024 * 1024).max_segments(20)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct EngineConfig {
+    pub backend: BackendKind,
+    // size in bytes after which the active segment is sealed and a new one started
+    // (for the LSM backend, the memtable size that triggers a flush to a new table)
+    pub segment_size: u64,
+    // number of segments (or LSM tables) tolerated before compaction kicks in
+    pub max_segments: usize,
+    pub compaction: CompactionPolicy,
+    pub durability: Durability,
+    // codec for record values written from now on, existing records keep theirs
+    pub compression: Compression,
+    // key of an encrypted database, also encrypts a new one. Never read from or written to `config.json`.
+    #[serde(skip)]
+    pub encryption: Option<EncryptionKey>,
+    // keep every sealed log under `<db>/archive/` for point-in-time recovery (`Engine::restore_to`).
+    // The archive only grows, old logs can be deleted from it by hand along with their archive.json entries.
+    pub archive: bool,
+    // open without taking the writer lock, next to a process that has the database open for writing.
+    // Reads see the database as it was when it was opened, writes fail with `DbError::ReadOnly`.
+    // Every segment or table it read at open stays open, so compactions by the writer don't affect it.
+    #[serde(skip)]
+    pub read_only: bool,
+    // segment (or table) files kept open for reads, the least recently used is closed once there are more
+    pub max_open_files: usize,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            backend: BackendKind::default(),
+            segment_size: 10 * 1000 * 1000,
+            max_segments: 50,
+            compaction: CompactionPolicy::default(),
+            durability: Durability::default(),
+            compression: Compression::default(),
+            encryption: None,
+            archive: false,
+            read_only: false,
+            max_open_files: 64,
+        }
+    }
+}
+
+impl EngineConfig {
+    pub const FILE_NAME: &'static str = "config.json";
+
+    // -> Read config from a JSON file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, DbError> {
+        let data = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    // -> Read `config.json` from a database directory, or use the defaults if there is none.
+    //    Unless it names a backend, an existing database keeps the one it was created with.
+    pub fn load(dir: impl AsRef<Path>) -> Result<Self, DbError> {
+        let path = dir.as_ref().join(Self::FILE_NAME);
+        let (mut config, names_backend) = if path.exists() {
+            let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+            (serde_json::from_value(json.clone())?, json.get("backend").is_some())
+        } else {
+            (Self::default(), false)
+        };
+
+        if !names_backend && let Some(backend) = BackendKind::of_database(dir.as_ref()) {
+            config.backend = backend;
+        }
+        Ok(config)
+    }
+
+    pub fn backend(mut self, backend: BackendKind) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn segment_size(mut self, bytes: u64) -> Self {
+        self.segment_size = bytes;
+        self
+    }
+
+    pub fn max_segments(mut self, count: usize) -> Self {
+        self.max_segments = count;
+        self
+    }
+
+    pub fn compaction(mut self, policy: CompactionPolicy) -> Self {
+        self.compaction = policy;
+        self
+    }
+
+    pub fn durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn encryption(mut self, key: EncryptionKey) -> Self {
+        self.encryption = Some(key);
+        self
+    }
+
+    pub fn archive(mut self, enabled: bool) -> Self {
+        self.archive = enabled;
+        self
+    }
+
+    pub fn read_only(mut self, enabled: bool) -> Self {
+        self.read_only = enabled;
+        self
+    }
+
+    pub fn max_open_files(mut self, count: usize) -> Self {
+        self.max_open_files = count;
+        self
+    }
+}
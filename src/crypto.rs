@@ -0,0 +1,154 @@
+use std::{path::{Path, PathBuf}, sync::Arc};
+
+use argon2::Argon2;
+use chacha20poly1305::{aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng, Payload}, XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::DbError, storage::write_file_atomic};
+
+// Encrypted databases keep `encryption.json` in their directory holding the KDF salt and a sealed
+// check value, so a wrong key is caught on open instead of surfacing as corrupt records.
+// Sealed data is [nonce: 24][ciphertext + poly1305 tag: 16], XChaCha20-Poly1305 with random nonces.
+// Whole files (manifests, hints, filters, schemas) are sealed behind a magic: [b"ENSE"][sealed],
+// bound to the file's name so one sealed file can't stand in for another.
+
+pub const META_FILE: &str = "encryption.json";
+const NONCE_LEN: usize = 24;
+const FILE_MAGIC: &[u8; 4] = b"ENSE";
+const CHECK: &[u8] = b"enso encryption check";
+
+// -> Where the key of an encrypted database comes from
+#[derive(Clone, PartialEq)]
+pub enum EncryptionKey {
+    // stretched into a key with Argon2id and the database's salt
+    Passphrase(String),
+    // file holding exactly 32 random bytes used as the key, e.g. `head -c 32 /dev/urandom > enso.key`
+    KeyFile(PathBuf),
+}
+
+impl std::fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EncryptionKey::Passphrase(_) => write!(f, "Passphrase(***)"),
+            EncryptionKey::KeyFile(path) => write!(f, "KeyFile({:?})", path),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncryptionMeta {
+    salt: Vec<u8>,
+    check: Vec<u8>,
+}
+
+pub struct Cipher {
+    aead: XChaCha20Poly1305,
+}
+
+impl Cipher {
+    fn new(key: &[u8; 32]) -> Self {
+        Self { aead: XChaCha20Poly1305::new(key.into()) }
+    }
+
+    // -> Encrypt and authenticate data, aad is authenticated but not stored
+    pub fn seal(&self, data: &[u8], aad: &[u8]) -> Vec<u8> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let sealed = self.aead.encrypt(&nonce, Payload { msg: data, aad }).expect("encryption failed");
+
+        let mut out = Vec::with_capacity(NONCE_LEN + sealed.len());
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&sealed);
+        out
+    }
+
+    // -> Decrypt sealed data, None if it was tampered with or sealed under another key
+    pub fn open(&self, data: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+        if data.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, sealed) = data.split_at(NONCE_LEN);
+        self.aead.decrypt(XNonce::from_slice(nonce), Payload { msg: sealed, aad }).ok()
+    }
+
+    fn derive(key: &EncryptionKey, salt: &[u8]) -> Result<Self, DbError> {
+        let mut bytes = [0u8; 32];
+        match key {
+            EncryptionKey::Passphrase(passphrase) => {
+                Argon2::default()
+                    .hash_password_into(passphrase.as_bytes(), salt, &mut bytes)
+                    .map_err(|_| DbError::WrongEncryptionKey)?;
+            }
+            EncryptionKey::KeyFile(path) => {
+                let data = std::fs::read(path)?;
+                bytes = data.try_into().map_err(|_| DbError::WrongEncryptionKey)?;
+            }
+        }
+        Ok(Self::new(&bytes))
+    }
+}
+
+// -> Cipher for the database in dir: checks the key against an encrypted database, sets up
+//    encryption for a new one (unless read_only), and refuses to mix a key with a database that isn't encrypted
+pub fn unlock(dir: &Path, key: Option<&EncryptionKey>, read_only: bool) -> Result<Option<Arc<Cipher>>, DbError> {
+    let meta_path = dir.join(META_FILE);
+
+    if meta_path.exists() {
+        let key = key.ok_or(DbError::EncryptionKeyRequired)?;
+        let meta: EncryptionMeta = serde_json::from_str(&std::fs::read_to_string(&meta_path)?)?;
+        let cipher = Cipher::derive(key, &meta.salt)?;
+        if cipher.open(&meta.check, META_FILE.as_bytes()).as_deref() != Some(CHECK) {
+            return Err(DbError::WrongEncryptionKey);
+        }
+        return Ok(Some(Arc::new(cipher)));
+    }
+
+    let Some(key) = key else {
+        return Ok(None);
+    };
+
+    // existing plaintext data would stay readable next to the encrypted records
+    if dir.join("manifest.json").exists() || dir.join("lsm").join("manifest.json").exists() {
+        return Err(DbError::NotEncrypted);
+    }
+    if read_only {
+        return Err(DbError::DatabaseNotFound);
+    }
+
+    std::fs::create_dir_all(dir)?;
+    let mut salt = vec![0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let cipher = Cipher::derive(key, &salt)?;
+    let meta = EncryptionMeta { check: cipher.seal(CHECK, META_FILE.as_bytes()), salt };
+    write_file_atomic(&meta_path, serde_json::to_string_pretty(&meta)?.as_bytes())?;
+
+    Ok(Some(Arc::new(cipher)))
+}
+
+// -> Contents for the file at path, sealed when the database is encrypted
+pub fn seal_file(cipher: Option<&Cipher>, data: Vec<u8>, path: &Path) -> Vec<u8> {
+    match cipher {
+        Some(cipher) => {
+            let mut out = FILE_MAGIC.to_vec();
+            out.extend_from_slice(&cipher.seal(&data, &file_aad(path)));
+            out
+        }
+        None => data,
+    }
+}
+
+// -> Plaintext of the file at path, written with `seal_file`
+pub fn open_file(cipher: Option<&Cipher>, data: Vec<u8>, path: &Path) -> std::io::Result<Vec<u8>> {
+    let Some(cipher) = cipher else {
+        return Ok(data);
+    };
+
+    data.strip_prefix(FILE_MAGIC.as_slice())
+        .and_then(|sealed| cipher.open(sealed, &file_aad(path)))
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "file failed to decrypt"))
+}
+
+fn file_aad(path: &Path) -> Vec<u8> {
+    let mut aad = FILE_MAGIC.to_vec();
+    aad.extend_from_slice(path.file_name().unwrap_or_default().as_encoded_bytes());
+    aad
+}
@@ -0,0 +1,77 @@
+use std::{sync::{Condvar, Mutex}, time::{Duration, Instant}};
+
+use serde::{Deserialize, Serialize};
+
+// -> How hard the engine tries to get an acknowledged write onto disk
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    // leave it to the OS page cache, a crash or power loss can lose recent writes
+    #[default]
+    None,
+    // fsync the segment after every record
+    FlushEveryWrite,
+    // writers wait for a shared fsync, issued once `max_batch` writes are pending
+    // or `interval` has passed since the batch started
+    GroupCommit { interval: Duration, max_batch: usize },
+}
+
+#[derive(Default)]
+struct CommitState {
+    // highest write sequence known to be on disk
+    synced: u64,
+    // highest write sequence waiting for a sync
+    requested: u64,
+    // whether some writer is currently collecting a batch and syncing it
+    leader: bool,
+}
+
+// -> Leader based group commit: the first waiting writer collects a batch, issues a single
+//    fsync covering everything appended so far and wakes up everyone it covered
+#[derive(Default)]
+pub struct GroupCommit {
+    state: Mutex<CommitState>,
+    cond: Condvar,
+}
+
+impl GroupCommit {
+    // -> Block until the write with sequence `seq` is on disk, `sync` persists everything
+    //    appended so far and returns the last write sequence it covered
+    pub fn wait(&self, seq: u64, sync: impl FnOnce() -> std::io::Result<u64>, interval: Duration, max_batch: usize) -> std::io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.requested = state.requested.max(seq);
+        self.cond.notify_all();
+
+        loop {
+            if state.synced >= seq {
+                return Ok(());
+            }
+            if !state.leader {
+                break;
+            }
+            state = self.cond.wait(state).unwrap();
+        }
+
+        // become the leader and give other writers a chance to join the batch
+        state.leader = true;
+        let deadline = Instant::now() + interval;
+        while state.requested - state.synced < max_batch as u64 {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            state = self.cond.wait_timeout(state, deadline - now).unwrap().0;
+        }
+        drop(state);
+
+        let result = sync();
+
+        let mut state = self.state.lock().unwrap();
+        state.leader = false;
+        if let Ok(target) = result {
+            state.synced = state.synced.max(target);
+        }
+        self.cond.notify_all();
+
+        result.map(|_| ())
+    }
+}
@@ -1,333 +1,377 @@
-use std::{collections::{HashMap, HashSet}, num::NonZeroUsize, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex, RwLock}, time::{SystemTime, UNIX_EPOCH}};
-use lru::LruCache;
-use serde::{de::DeserializeOwned, Serialize};
-use crate::{record::Record, storage::Storage, types::SegIndex, utils::{from_bytes, to_bytes}};
-
-// const MAX_SEGMENTS: usize = 50;
-const MAX_SEGMENTS: usize = 3;
+use std::{fs::File, path::{Path, PathBuf}, sync::{Arc, Condvar, Mutex, MutexGuard}, time::Duration};
+use crate::{backend::{LogBackend, LsmBackend, StorageBackend}, batch::WriteBatch, config::{BackendKind, CompactionPolicy, EngineConfig}, crypto::{self, Cipher, EncryptionKey}, durability::{Durability, GroupCommit}, error::DbError, memory::MemoryBackend, record::Record, snapshot::Snapshot, storage::{copy_dir, copy_file, lock_dir}, types::{CompactionStats, CompressionStats, RecoveryReport}, utils::now_secs};
 
 pub struct Engine {
-    pub storage: Arc<Mutex<Storage>>,
-    pub index: Arc<RwLock<LruCache<String, SegIndex>>>,
-    compaction_running: Arc<AtomicBool>,
+    pub storage: Arc<dyn StorageBackend>,
+    compactions: Arc<Compactions>,
+    config: EngineConfig,
+    // kept apart from config, it can be changed on an engine shared between sessions
+    durability: Mutex<Durability>,
+    group_commit: Arc<GroupCommit>,
+    // set when the database is encrypted, shared with whatever else writes into its directory
+    cipher: Option<Arc<Cipher>>,
+    // database directory, None for engines not opened from one
+    path: Option<PathBuf>,
+    // writer lock of the directory, None when opened read-only or not from a directory
+    _lock: Option<File>,
 }
 
 impl Engine {
-    pub fn new() -> Self {
-        let mut storage = Storage::new();
-        let index = Arc::new(RwLock::new(
-            storage.rebuild_index().unwrap_or_else(|_| LruCache::new(NonZeroUsize::new(4).unwrap()))
-        ));
-        
-        Self {
-            storage: Arc::new(Mutex::new(storage)),
-            index,
-            compaction_running: Arc::new(AtomicBool::new(false)),
+    // -> Open an engine whose data (manifest, segments, indexes) lives under the given directory,
+    //    configured by the directory's `config.json` if it has one
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, DbError> {
+        let config = EngineConfig::load(&path)?;
+        Self::open_with_config(path, config)
+    }
+
+    // -> Fails if the database is encrypted and `config.encryption` is missing or holds the wrong key,
+    //    with `DbError::DatabaseLocked` if another engine has it open for writing (see `EngineConfig::read_only`),
+    //    or with `DbError::ConfigMismatch` if it was created with another backend
+    pub fn open_with_config(path: impl AsRef<Path>, config: EngineConfig) -> Result<Self, DbError> {
+        // the lock comes first, a second writer mustn't get as far as setting up encryption.
+        // A read-only open must not leave anything behind, not even that setup for a new database.
+        let lock = if config.read_only {
+            if !Self::exists(path.as_ref()) {
+                return Err(DbError::DatabaseNotFound);
+            }
+            None
+        } else {
+            std::fs::create_dir_all(&path)?;
+            Some(lock_dir(path.as_ref())?)
+        };
+        // the backend is fixed when the database is created, the other one would start empty next to it
+        if BackendKind::of_database(path.as_ref()).is_some_and(|backend| backend != config.backend) {
+            return Err(DbError::ConfigMismatch { setting: "backend".to_string() });
         }
+        let cipher = crypto::unlock(path.as_ref(), config.encryption.as_ref(), config.read_only)?;
+        let storage: Arc<dyn StorageBackend> = match config.backend {
+            BackendKind::Log => Arc::new(LogBackend::open(&path, config.clone(), cipher.clone())?),
+            BackendKind::Lsm => Arc::new(LsmBackend::open(&path, config.clone(), cipher.clone())?),
+        };
+
+        let mut engine = Self::with_backend(storage, config);
+        engine.cipher = cipher;
+        engine.path = Some(path.as_ref().to_path_buf());
+        engine._lock = lock;
+        Ok(engine)
     }
 
-    fn active_segment(&self) -> String {
-        let storage = self.storage.lock().unwrap();
-        storage.manifest.active_segment.clone()
+    // -> Engine that keeps everything in memory and never touches the filesystem
+    pub fn in_memory(config: EngineConfig) -> Self {
+        Self::with_backend(Arc::new(MemoryBackend::new()), config)
     }
 
-    // fn get_or_load_seg_index(&mut self, seg: &str) -> &mut SegIndex {
-    //     if !self.index.contains(seg) {
-    //         let name = &seg[..seg.rfind('.').unwrap()];
-    //         let idx_path = format!("data/index/{}.idx", name);
-    //         let mut storage = self.storage.lock().unwrap();
-    //         let map = storage.load_idx(&idx_path).unwrap_or_default();
-    //         self.index.put(seg.to_string(), map);
-    //     }
-    //
-    //     self.index.get_mut(seg).unwrap()
-    // }
+    // -> Engine on top of any storage backend
+    pub fn with_backend(storage: Arc<dyn StorageBackend>, config: EngineConfig) -> Self {
+        storage.set_durability(config.durability);
 
-    fn ensure_seg_index_loaded(&self, seg: &str) {
-        {
-            let index = self.index.read().unwrap();
-            if index.contains(seg) {
-                return;
-            }
+        Self {
+            storage,
+            compactions: Arc::default(),
+            durability: Mutex::new(config.durability),
+            config,
+            group_commit: Arc::new(GroupCommit::default()),
+            cipher: None,
+            path: None,
+            _lock: None,
         }
+    }
 
-        // If segment is not present
-        let map = {
-            let storage = self.storage.lock().unwrap();
-            let name = &seg[..seg.rfind('.').unwrap()];
-            let base = storage.get_base();
-            // let idx_path = format!("data/index/{}.idx", name);
-            let idx_path = base.join("index").join(format!("{}.idx", name));
-            storage.load_idx(&idx_path).unwrap_or_default()
-        };
+    // -> Choose how writes are persisted, see `Durability`
+    pub fn set_durability(&self, durability: Durability) {
+        *self.durability.lock().unwrap() = durability;
+        self.storage.set_durability(durability);
+    }
 
-        let mut index = self.index.write().unwrap();
-        index.put(seg.to_string(), map);
+    pub fn durability(&self) -> Durability {
+        *self.durability.lock().unwrap()
     }
 
-    fn maybe_compact(&mut self) {
-        let seg_count = {
-            let storage = self.storage.lock().unwrap();
-            storage.manifest.segments.len()
-        };
+    // -> Settings the engine was opened with, see `durability` for the current durability mode
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
 
-        // check if number of segments exceeds threshold
-        if seg_count <= MAX_SEGMENTS {
-            return;
+    pub(crate) fn cipher(&self) -> Option<Arc<Cipher>> {
+        self.cipher.clone()
+    }
+
+    // -> Fails for a database opened read-only
+    pub(crate) fn check_writable(&self) -> Result<(), DbError> {
+        if self.config.read_only {
+            return Err(DbError::ReadOnly);
         }
+        Ok(())
+    }
 
-        // return if compaction already running
-        if self.compaction_running.swap(true, Ordering::SeqCst) {
-            return;
+    // -> Wait until the write with the given sequence is persisted, according to the durability mode
+    fn wait_durable(&self, seq: u64) -> std::io::Result<()> {
+        match self.durability() {
+            Durability::GroupCommit { interval, max_batch } => {
+                self.group_commit.wait(seq, || self.storage.sync(), interval, max_batch)
+            }
+            // None doesn't wait, FlushEveryWrite already synced inside append
+            _ => Ok(()),
         }
+    }
 
-        let storage = Arc::clone(&self.storage);
-        let index = Arc::clone(&self.index);
-        let compaction_flag = Arc::clone(&self.compaction_running);
+    // -> What was repaired while opening the storage
+    pub fn recovery_report(&self) -> RecoveryReport {
+        self.storage.recovery_report()
+    }
 
-        std::thread::spawn(move || {
-            let result = {
-                let mut storage = storage.lock().unwrap();
-                storage.compact_segments()
-            };
-
-            if let Err(e) = result {
-                eprintln!("[EnsoDB] Compaction failed: {}", e);
-            } else if let Ok((removed, new_seg)) = result {
-                let mut index = index.write().unwrap();
-
-                for seg in removed {
-                    index.pop(&seg);
-                }
+    // -> Compact sealed segments now, waits for a background compaction to finish first
+    pub fn compact(&self) -> Result<(), DbError> {
+        self.check_writable()?;
+        self.compactions.wait_idle().running = true;
 
-                // load new segment index
-                let idx = {
-                    let storage = storage.lock().unwrap();
-                    let base = storage.get_base();
-                    // storage.load_idx(format!("data/index/{}.idx", &new_seg[..new_seg.rfind('.').unwrap()]).as_str()).unwrap_or_default()
-                    storage.load_idx(&base.join("index").join(format!("{}.idx", &new_seg[..new_seg.rfind('.').unwrap()]).as_str())).unwrap_or_default()
-                };
-                index.put(new_seg, idx);
-            }
+        let result = self.storage.compact();
+        self.compactions.finish(result.as_ref().err());
+        result
+    }
 
-            compaction_flag.store(false, Ordering::SeqCst);
-        });
+    // -> Copy a consistent snapshot of the database into dest (a directory that doesn't exist yet)
+    //    while reads and writes carry on, see `Engine::restore` to bring it back
+    pub fn backup(&self, dest: impl AsRef<Path>) -> Result<(), DbError> {
+        let dest = dest.as_ref();
+        if dest.exists() {
+            return Err(DbError::DatabaseExists);
+        }
 
-        // match self.storage.compact_segments() {
-        //     Ok(removed) => {
-        //         // clean up LRU index/cache
-        //         for seg in removed {
-        //             self.index.pop(&seg);
-        //         }
-        //     }
-        //     Err(e) => {
-        //         eprintln!("[EnsoDB] Compaction failed: {}", e);
-        //     }
-        // }
-    }
-
-    // pub fn set<T: Serialize>(&mut self, key: String, value: T) {
-    //     let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_secs();
-    //
-    //     let encoded_value = to_bytes(&value);
-    //     let record = Record::new(key.clone(), encoded_value, now, false);
-    //     // let mut storage = self.storage.lock().unwrap();
-    //
-    //     let offset = {
-    //         let mut storage = self.storage.lock().unwrap();
-    //         storage.append(&record)
-    //     };
-    //
-    //     if let Err(e) = offset {
-    //         eprintln!("[EnsoDB error] Error while storing: {}", e);
-    //     } else {
-    //         let seg = self.active_segment();
-    //         self.ensure_seg_index_loaded(&seg);
-    //
-    //         {
-    //             let mut index = self.index.write().unwrap();
-    //             index.get_mut(&seg).unwrap().insert(key.clone(), offset.unwrap());
-    //         }
-    //
-    //         // check for compaction
-    //         self.maybe_compact();
-    //     }
-    // }
-
-    // pub fn get<T: DeserializeOwned>(&mut self, key: String) -> Option<T> {
-    //     // let segments: Vec<String> = storage.manifest.segments.iter().cloned().collect();
-    //     let segments = {
-    //         let storage = self.storage.lock().unwrap();
-    //         storage.manifest.segments.clone()
-    //     };
-    //     for seg in segments.into_iter().rev() {
-    //         // let seg_idx = self.get_or_load_seg_index(&seg);
-    //         self.ensure_seg_index_loaded(&seg);
-    //
-    //         let offset = {
-    //             let mut index = self.index.write().unwrap();
-    //             index.get(&seg)?.get(&key).copied()
-    //         };
-    //
-    //         if let Some(offset) = offset {
-    //             let mut storage = self.storage.lock().unwrap();
-    //             let record = storage.read_from_segment(&seg, offset).ok()?;
-    //             if record.deleted { return None; }
-    //             return Some(from_bytes(&record.value));
-    //         }
-    //
-    //     }
-    //
-    //     None
-    // }
-
-    // pub fn delete(&mut self, key: String) {
-    //     let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_secs();
-    //     let record = Record::new(key.clone(), vec![0; 17], now, true);
-    //
-    //     let offset = {
-    //         let mut storage = self.storage.lock().unwrap();
-    //         storage.append(&record)
-    //     };
-    //
-    //     if let Err(e) = offset {
-    //         eprintln!("[EnsoDB error] Error while deleting: {}", e);
-    //     } else {
-    //         let seg = self.active_segment();
-    //         self.ensure_seg_index_loaded(&seg);
-    //
-    //         {
-    //             let mut index = self.index.write().unwrap();
-    //             index.get_mut(&seg).unwrap().insert(key.clone(), offset.unwrap());
-    //         }
-    //
-    //         // check for compaction
-    //         self.maybe_compact();
-    //     }
-    // }
-
-    pub fn set_raw(&mut self, key: String, value: Vec<u8>) {
-        let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_secs();
-
-        let record = Record::new(key.clone(), value, now, false);
-
-        let offset = {
-            let mut storage = self.storage.lock().unwrap();
-            storage.append(&record)
-        };
+        self.storage.backup(dest)?;
 
-        if let Err(e) = offset {
-            eprintln!("[EnsoDB error] Error while storing: {}", e);
-            return;
+        // settings and the key check, without them an encrypted backup can't be opened
+        if let Some(path) = &self.path {
+            for name in [EngineConfig::FILE_NAME, crypto::META_FILE] {
+                if path.join(name).exists() {
+                    copy_file(&path.join(name), &dest.join(name), None)?;
+                }
+            }
         }
 
-        let seg = self.active_segment();
-        self.ensure_seg_index_loaded(&seg);
+        Ok(())
+    }
 
-        {
-            let mut index = self.index.write().unwrap();
-            index.get_mut(&seg).unwrap().insert(key, offset.unwrap());
+    // -> Bring a backup back as the database at path (which must not exist yet). The backup is copied
+    //    next to path and every record checked against its checksum before the copy is renamed into
+    //    place, so a damaged backup never becomes a database. key is needed for encrypted backups.
+    pub fn restore(backup: impl AsRef<Path>, path: impl AsRef<Path>, key: Option<&EncryptionKey>) -> Result<(), DbError> {
+        let path = path.as_ref();
+        if path.exists() {
+            return Err(DbError::DatabaseExists);
         }
 
-        self.maybe_compact();
+        let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".restore");
+        let tmp = path.with_file_name(tmp_name);
+        let _ = std::fs::remove_dir_all(&tmp);
+
+        let result = copy_dir(backup.as_ref(), &tmp)
+            .map_err(DbError::from)
+            .and_then(|_| Self::verify(&tmp, key));
+        if let Err(e) = result {
+            let _ = std::fs::remove_dir_all(&tmp);
+            return Err(e);
+        }
+
+        std::fs::rename(&tmp, path)?;
+        Ok(())
     }
 
-    pub fn get_raw(&self, key: &str) -> Option<Vec<u8>> {
-        let segments = {
-            let storage = self.storage.lock().unwrap();
-            storage.manifest.segments.clone()
-        };
+    // -> Write the database as it was at `timestamp` (unix seconds) into dest, a directory that doesn't
+    //    exist yet, e.g. to get rows back from before an accidental delete. Needs `EngineConfig::archive`,
+    //    history from before the archive was started isn't available. The new database is encrypted
+    //    with key if one is given, and plaintext otherwise.
+    pub fn restore_to(&self, timestamp: u64, dest: impl AsRef<Path>, key: Option<&EncryptionKey>) -> Result<(), DbError> {
+        let dest = dest.as_ref();
+        if dest.exists() {
+            return Err(DbError::DatabaseExists);
+        }
 
-        for seg in segments.into_iter().rev() {
-            self.ensure_seg_index_loaded(&seg);
+        let records = self.storage.records_at(timestamp)?;
+        // a new database to write the records into, whether this one is read-only or archives its logs
+        let config = EngineConfig { encryption: key.cloned(), read_only: false, archive: false, ..self.config.clone() };
+        let engine = Engine::open_with_config(dest, config)?;
+        for record in &records {
+            engine.storage.append(record)?;
+        }
+        engine.storage.sync()?;
 
-            let offset = {
-                let mut index = self.index.write().unwrap();
-                index.get(&seg)?.get(key).copied()
-            };
+        if let Some(path) = self.path.as_ref().map(|p| p.join(EngineConfig::FILE_NAME)).filter(|p| p.exists()) {
+            copy_file(&path, &dest.join(EngineConfig::FILE_NAME), None)?;
+        }
 
-            if let Some(offset) = offset {
-                let mut storage = self.storage.lock().unwrap();
-                let record = storage.read_from_segment(&seg, offset).ok()?;
-                if record.deleted {
-                    return None;
-                }
-                return Some(record.value);
-            }
+        Ok(())
+    }
+
+    // -> Whether dir holds a database of either backend
+    fn exists(dir: &Path) -> bool {
+        BackendKind::of_database(dir).is_some()
+    }
+
+    // -> Check every record of the database in dir, whichever backend stored it
+    fn verify(dir: &Path, key: Option<&EncryptionKey>) -> Result<(), DbError> {
+        let log = dir.join("manifest.json").exists();
+        let lsm = dir.join("lsm").join("manifest.json").exists();
+        if !log && !lsm {
+            return Err(DbError::DatabaseNotFound);
         }
 
-        None
+        let cipher = crypto::unlock(dir, key, true)?;
+        if log {
+            LogBackend::verify(dir, cipher.clone())?;
+        }
+        if lsm {
+            LsmBackend::verify(dir, cipher)?;
+        }
+        Ok(())
     }
 
-    pub fn delete_raw(&mut self, key: String) {
-        let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_secs();
-        let record = Record::new(key.clone(), Vec::new(), now, true);
+    // -> Counters for compactions run since open, including how long they kept writers waiting
+    //    and why the last one failed if it did
+    pub fn compaction_stats(&self) -> CompactionStats {
+        let mut stats = self.storage.compaction_stats();
+        stats.last_error = self.compactions.state.lock().unwrap().last_error.clone();
+        stats
+    }
 
-        let offset = {
-            let mut storage = self.storage.lock().unwrap();
-            storage.append(&record)
-        };
+    // -> How well record values compressed, see `EngineConfig::compression`
+    pub fn compression_stats(&self) -> CompressionStats {
+        self.storage.compression_stats()
+    }
 
-        if let Err(e) = offset {
-            eprintln!("[EnsoDB error] Error while deleting: {}", e);
+    fn maybe_compact(&self) {
+        let seg_count = self.storage.segment_count();
+
+        // check if number of segments exceeds threshold
+        if self.config.compaction == CompactionPolicy::Manual || seg_count <= self.config.max_segments {
             return;
         }
 
-        let seg = self.active_segment();
-        self.ensure_seg_index_loaded(&seg);
-
+        // return if compaction already running
         {
-            let mut index = self.index.write().unwrap();
-            index.get_mut(&seg).unwrap().insert(key, offset.unwrap());
+            let mut state = self.compactions.state.lock().unwrap();
+            if state.running {
+                return;
+            }
+            state.running = true;
         }
 
+        let storage = Arc::clone(&self.storage);
+        let compactions = Arc::clone(&self.compactions);
+
+        std::thread::spawn(move || {
+            let result = storage.compact();
+            // before it's marked finished, a dropped engine waits on that to release the storage
+            drop(storage);
+            compactions.finish(result.as_ref().err());
+        });
+    }
+
+    pub fn set_raw(&self, key: String, value: Vec<u8>) -> Result<(), DbError> {
+        let record = Record::new(key, value, now_secs(), false);
+        self.store(record)
+    }
+
+    // -> Store a value that expires once ttl (whole seconds, at least one) has passed.
+    //    Reads stop returning it from then on and compaction drops it.
+    pub fn set_raw_with_ttl(&self, key: String, value: Vec<u8>, ttl: Duration) -> Result<(), DbError> {
+        let record = Record::new(key, value, now_secs(), false).with_ttl(ttl.as_secs().max(1));
+        self.store(record)
+    }
+
+    // -> Append a record and wait for it to be durable, a failed write is returned and never compacted after
+    fn store(&self, record: Record) -> Result<(), DbError> {
+        self.check_writable()?;
+        let seq = self.storage.append(&record)?;
+        self.wait_durable(seq)?;
         self.maybe_compact();
+        Ok(())
     }
 
-    pub fn scan_prefix(&self, prefix: &str) -> Vec<(String, Vec<u8>)> {
-        let segments = {
-            let storage = self.storage.lock().unwrap();
-            storage.manifest.segments.clone()
-        };
+    pub fn get_raw(&self, key: &str) -> Result<Option<Vec<u8>>, DbError> {
+        let record = self.storage.get(key)?;
+        let now = now_secs();
+        Ok(record.filter(|r| !r.is_expired(now)).map(|r| r.value))
+    }
 
-        let mut seen = HashSet::new();
-        let mut results = Vec::new();
+    pub fn delete_raw(&self, key: String) -> Result<(), DbError> {
+        let record = Record::new(key, Vec::new(), now_secs(), true);
+        self.store(record)
+    }
 
-        for seg in segments.into_iter().rev() {
-            self.ensure_seg_index_loaded(&seg);
+    // -> Apply a batch of puts and deletes atomically, every record stamped with the same time.
+    //    After a crash either all of them are there or none.
+    pub fn write(&self, batch: WriteBatch) -> Result<(), DbError> {
+        self.write_batch(batch, None)
+    }
 
-            let offsets = {
-                let mut index = self.index.write().unwrap();
-                match index.get(&seg) {
-                    Some(map) => map.clone(),
-                    None => continue,
-                }
-            };
+    // -> `write`, unless a key of the batch was written after snapshot was taken: then nothing is
+    //    written and it fails with `DbError::TransactionConflict`. Commits transactions.
+    pub fn write_since(&self, batch: WriteBatch, snapshot: &Snapshot) -> Result<(), DbError> {
+        self.write_batch(batch, Some(snapshot.seq()))
+    }
 
-            for (key, offset) in offsets {
-                if !key.starts_with(prefix) {
-                    continue;
-                }
+    fn write_batch(&self, batch: WriteBatch, since: Option<u64>) -> Result<(), DbError> {
+        self.check_writable()?;
+        if batch.is_empty() {
+            return Ok(());
+        }
 
-                if seen.contains(&key) {
-                    continue;
-                }
+        let now = now_secs();
+        let records: Vec<Record> = batch.into_records().into_iter().map(|record| Record { timestamp: now, ..record }).collect();
 
-                let record = {
-                    let mut storage = self.storage.lock().unwrap();
-                    storage.read_from_segment(&seg, offset)
-                };
+        let seq = match since {
+            Some(since) => self.storage.append_batch_since(&records, since)?,
+            None => self.storage.append_batch(&records)?,
+        };
+        self.wait_durable(seq)?;
+        self.maybe_compact();
+        Ok(())
+    }
 
-                if let Ok(record) = record {
-                    seen.insert(key.clone());
+    // -> Read through a snapshot, so a long scan doesn't hold up writers
+    pub fn scan_prefix(&self, prefix: &str) -> Result<Vec<(String, Vec<u8>)>, DbError> {
+        self.snapshot().scan_prefix(prefix)
+    }
 
-                    if !record.deleted {
-                        results.push((key, record.value));
-                    }
-                }
-            }
-        }
+    // -> Consistent view of the database as of now, unaffected by writes made while it's held
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(Arc::clone(&self.storage))
+    }
+}
+
+impl Drop for Engine {
+    // a background compaction still uses the storage, the directory lock is only let go of after it
+    fn drop(&mut self) {
+        drop(self.compactions.wait_idle());
+    }
+}
+
+// -> Whether a compaction is running, shared with the background compaction thread
+#[derive(Default)]
+struct Compactions {
+    state: Mutex<CompactionState>,
+    // notified whenever a compaction finishes
+    finished: Condvar,
+}
+
+#[derive(Default)]
+struct CompactionState {
+    running: bool,
+    last_error: Option<String>,
+}
+
+impl Compactions {
+    // -> Wait for a running compaction to finish, the state stays locked so another can be started
+    fn wait_idle(&self) -> MutexGuard<'_, CompactionState> {
+        self.finished.wait_while(self.state.lock().unwrap(), |state| state.running).unwrap()
+    }
 
-        results
+    fn finish(&self, error: Option<&DbError>) {
+        let mut state = self.state.lock().unwrap();
+        state.running = false;
+        state.last_error = error.map(|e| format!("{:?}", e));
+        self.finished.notify_all();
     }
 }
@@ -1,6 +1,4 @@
-use std::string::FromUtf8Error;
-
-use crate::{sql::lexer::Token, types::DataType};
+use std::{io::ErrorKind, string::FromUtf8Error};
 
 #[derive(Debug)]
 pub enum DbError {
@@ -24,6 +22,41 @@ pub enum DbError {
     DuplicatePrimaryKey,
     PrimaryKeyMissing,
 
+    Corruption { segment: String, offset: u64 },
+    // a manifest that can't be decrypted or parsed, path is the file
+    CorruptedManifest { path: String },
+    // a segment (or table) the manifest lists is gone
+    MissingSegment { segment: String },
+    DiskFull,
+    PermissionDenied,
+    // another process (or engine) has the database open for writing
+    DatabaseLocked,
+    // a setting that disagrees with the database: with how it was created, or with the
+    // session that has it open in this process already
+    ConfigMismatch { setting: String },
+    // a write to a database opened with `EngineConfig::read_only`
+    ReadOnly,
+    // the database is encrypted and no key was given
+    EncryptionKeyRequired,
+    WrongEncryptionKey,
+    // a key was given for a database that was created without encryption
+    NotEncrypted,
+    // the operation needs a database stored on disk
+    NotPersistent,
+    // a read-only open of a database still in the store all databases used to share,
+    // it's moved into its own directory by the first open for writing (see migrate.rs)
+    NotMigrated { db: String },
+    // a statement a remote session can't run, see `Enso::remote`
+    NotAllowed { statement: String },
+    // point-in-time recovery needs `EngineConfig::archive`
+    ArchiveDisabled,
+    // BEGIN while a transaction is already open
+    TransactionInProgress,
+    // COMMIT or ROLLBACK without BEGIN
+    NoTransaction,
+    // COMMIT of a transaction that writes a key someone else wrote after it began, nothing was written
+    TransactionConflict,
+
     Io(std::io::Error),
     SerdeJsonError(serde_json::Error),
     Utf8(FromUtf8Error),
@@ -31,7 +64,21 @@ pub enum DbError {
 
 impl From<std::io::Error> for DbError {
     fn from(e: std::io::Error) -> Self {
-        DbError::Io(e)
+        match e.kind() {
+            ErrorKind::StorageFull => DbError::DiskFull,
+            ErrorKind::PermissionDenied => DbError::PermissionDenied,
+            _ => DbError::Io(e),
+        }
+    }
+}
+
+impl DbError {
+    // -> Report a segment (or table) file that isn't there as missing, other errors as they are
+    pub(crate) fn for_segment(self, segment: &str) -> Self {
+        match self {
+            DbError::Io(e) if e.kind() == ErrorKind::NotFound => DbError::MissingSegment { segment: segment.to_string() },
+            e => e,
+        }
     }
 }
 
@@ -0,0 +1,101 @@
+use std::{collections::HashMap, fs::File, path::{Path, PathBuf}, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex}};
+
+// Open read handles of segment files, shared by every reader of a storage so a lookup neither reopens
+// its segment nor needs the storage lock to read it. Reads go through `read_exact_at`, which doesn't
+// use the handle's cursor, so any number of them can share one handle at the same time.
+// Each handle also knows the length of its file, sealed segments never change and the writer
+// reports every append to the active one, so reads don't ask the filesystem for it.
+
+// -> Open file and how much of it there is to read
+pub struct Handle {
+    pub file: File,
+    len: AtomicU64,
+}
+
+impl Handle {
+    pub fn len(&self) -> u64 {
+        self.len.load(Ordering::Acquire)
+    }
+}
+
+// -> At most `capacity` open handles, the least recently used one is closed first
+pub struct HandleCache {
+    capacity: usize,
+    handles: Mutex<Handles>,
+}
+
+#[derive(Default)]
+struct Handles {
+    // handle of every open file with the tick it was last used at
+    files: HashMap<PathBuf, (Arc<Handle>, u64)>,
+    tick: u64,
+}
+
+impl HandleCache {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity: capacity.max(1), handles: Mutex::default() }
+    }
+
+    // -> Read handle of the file at path, opened if it isn't yet
+    pub fn get(&self, path: &Path) -> std::io::Result<Arc<Handle>> {
+        let mut handles = self.handles.lock().unwrap();
+        handles.tick += 1;
+        let tick = handles.tick;
+
+        if let Some((file, used)) = handles.files.get_mut(path) {
+            *used = tick;
+            return Ok(Arc::clone(file));
+        }
+
+        // measured under the lock, so an append reported by `set_len` while it opens isn't missed
+        let file = File::open(path)?;
+        let len = AtomicU64::new(file.metadata()?.len());
+        let file = Arc::new(Handle { file, len });
+        if handles.files.len() >= self.capacity {
+            let oldest = handles.files.iter().min_by_key(|(_, (_, used))| *used).map(|(path, _)| path.clone());
+            if let Some(oldest) = oldest {
+                handles.files.remove(&oldest);
+            }
+        }
+        handles.files.insert(path.to_path_buf(), (Arc::clone(&file), tick));
+        Ok(file)
+    }
+
+    // -> Record that the file at path is now len bytes long, after an append to it
+    pub fn set_len(&self, path: &Path, len: u64) {
+        if let Some((file, _)) = self.handles.lock().unwrap().files.get(path) {
+            file.len.store(len, Ordering::Release);
+        }
+    }
+
+    // -> Close the handle of a file that's being deleted, readers still holding it can finish
+    pub fn evict(&self, path: &Path) {
+        self.handles.lock().unwrap().files.remove(path);
+    }
+}
+
+// -> Fill buf with the bytes of file at offset, without seeking
+#[cfg(unix)]
+pub fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+// -> Fill buf with the bytes of file at offset. seek_read moves the cursor on Windows,
+//    which is fine as long as nothing reads these handles sequentially.
+#[cfg(windows)]
+pub fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    use std::{io::ErrorKind, os::windows::fs::FileExt};
+
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(read) => {
+                buf = &mut std::mem::take(&mut buf)[read..];
+                offset += read as u64;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use std::path::Path;
+
+use crate::{crypto::{open_file, seal_file, Cipher}, storage::write_file_atomic, types::{HintEntry, SegIndex}, utils::{decode_u32, encode_u32}};
+
+// Hint file layout (big endian), one entry per key holding that key's latest record in the segment:
+// [key_len: u32][key][offset: u64][timestamp: u64][deleted: u8] ... [crc32: u32]
+// The trailing checksum covers every entry, a hint failing it is ignored and rebuilt from its log.
+// In encrypted databases the whole file is sealed (see crypto.rs).
+
+pub fn write_hint(path: &Path, index: &SegIndex, cipher: Option<&Cipher>) -> std::io::Result<()> {
+    let mut entries: Vec<_> = index.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut buf = Vec::new();
+    for (key, entry) in entries {
+        buf.extend_from_slice(&encode_u32(key.len() as u32));
+        buf.extend_from_slice(key.as_bytes());
+        buf.extend_from_slice(&entry.offset.to_be_bytes());
+        buf.extend_from_slice(&entry.timestamp.to_be_bytes());
+        buf.push(entry.deleted as u8);
+    }
+
+    let crc = crc32fast::hash(&buf);
+    buf.extend_from_slice(&encode_u32(crc));
+
+    write_file_atomic(path, &seal_file(cipher, buf, path))
+}
+
+pub fn load_hint(path: &Path, cipher: Option<&Cipher>) -> std::io::Result<SegIndex> {
+    let buf = open_file(cipher, std::fs::read(path)?, path)?;
+    let invalid = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), msg));
+
+    if buf.len() < 4 {
+        return Err(invalid("truncated hint file"));
+    }
+
+    let (body, crc) = buf.split_at(buf.len() - 4);
+    if crc32fast::hash(body) != decode_u32(crc) {
+        return Err(invalid("hint checksum mismatch"));
+    }
+
+    let mut index = SegIndex::new();
+    let mut pos = 0;
+    while pos < body.len() {
+        let len_b = body.get(pos..pos + 4).ok_or_else(|| invalid("truncated hint entry"))?;
+        let key_len = decode_u32(len_b) as usize;
+        pos += 4;
+
+        let key_b = body.get(pos..pos + key_len).ok_or_else(|| invalid("truncated hint entry"))?;
+        pos += key_len;
+        let rest = body.get(pos..pos + 17).ok_or_else(|| invalid("truncated hint entry"))?;
+        pos += 17;
+
+        let key = String::from_utf8(key_b.to_vec()).map_err(|_| invalid("hint key not utf8"))?;
+        let offset = u64::from_be_bytes(rest[0..8].try_into().unwrap());
+        let timestamp = u64::from_be_bytes(rest[8..16].try_into().unwrap());
+        let deleted = rest[16] != 0;
+
+        index.insert(key, HintEntry { offset, timestamp, deleted });
+    }
+
+    Ok(index)
+}
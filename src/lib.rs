@@ -0,0 +1,45 @@
+mod api;
+mod archive;
+mod backend;
+mod batch;
+mod bloom;
+mod client;
+mod codec;
+mod compression;
+mod config;
+mod crypto;
+mod durability;
+mod engine;
+mod error;
+mod handles;
+mod hint;
+mod lsm;
+mod memory;
+mod migrate;
+mod record;
+mod registry;
+mod schema;
+mod snapshot;
+mod sql;
+mod sstable;
+mod storage;
+mod transaction;
+mod types;
+mod utils;
+
+pub use api::Enso;
+pub use backend::StorageBackend;
+pub use batch::WriteBatch;
+pub use client::EnsoDB;
+pub use compression::Compression;
+pub use config::{BackendKind, CompactionPolicy, EngineConfig};
+pub use crypto::EncryptionKey;
+pub use durability::{Durability, GroupCommit};
+pub use engine::Engine;
+pub use error::DbError;
+pub use memory::MemoryBackend;
+pub use record::Record;
+pub use snapshot::Snapshot;
+pub use transaction::Transaction;
+pub use sql::ast::{Expr, QueryResult, Stmt};
+pub use types::{Column, CompactionStats, CompressionStats, DataType, RecoveryReport, TableSchema, Value};
@@ -0,0 +1,564 @@
+use std::{collections::BTreeMap, fs::{File, OpenOptions}, io::{Read, Seek, SeekFrom}, path::{Path, PathBuf}, sync::{Arc, Mutex}, time::Instant};
+use chrono::Utc;
+use crate::{archive::{replay_records, Archive}, config::EngineConfig, crypto::{seal_file, Cipher}, durability::Durability, error::DbError, handles::{Handle, HandleCache}, record::{Location, Record}, snapshot::{overlay, Snapshots}, sstable::SsTable, storage::{append, load_manifest, write_file_atomic}, types::{CompactionStats, CompressionStats, LsmManifest, RecoveryReport}, utils::now_secs};
+
+// LSM layout under `<db>/lsm/`:
+// manifest.json   tables in age order
+// wal.log         records of the memtable, replayed on open and cleared once it's flushed
+// sst-000001.sst  immutable sorted tables (see sstable.rs)
+// sst-000001.bloom  Bloom filter of a table's keys (see bloom.rs)
+// With `EngineConfig::archive` on, the log is archived before every flush clears it (see archive.rs).
+
+const WAL: &str = "wal.log";
+
+pub fn table_name(id: u32) -> String {
+    format!("sst-{:06}.sst", id)
+}
+
+// -> Numeric id of a table file name (sst-000001.sst -> 1), None if it isn't one
+fn table_id(name: &str) -> Option<u32> {
+    name.strip_prefix("sst-")?.strip_suffix(".sst")?.parse().ok()
+}
+
+pub struct LsmStorage {
+    dir: PathBuf,
+    pub manifest: LsmManifest,
+    pub recovery: RecoveryReport,
+    pub config: EngineConfig,
+    pub compaction_stats: CompactionStats,
+    pub compression_stats: CompressionStats,
+    wal: File,
+    // write sequence of the last appended record, also tells which writes a sync covers
+    last_seq: u64,
+    // latest record of every key written since the last flush, tombstones included
+    memtable: BTreeMap<String, Record>,
+    memtable_bytes: u64,
+    // oldest first, shared with compaction jobs reading them outside the lock
+    tables: Vec<Arc<SsTable>>,
+    // open handles of the tables, shared with reads outside the lock
+    handles: Arc<HandleCache>,
+    // highest table id handed out so far
+    next_table: u32,
+    cipher: Option<Arc<Cipher>>,
+    // backups copying tables outside the lock and open snapshots reading them,
+    // tables compaction replaces meanwhile are kept until both are done
+    backups_running: usize,
+    retired: Vec<Arc<SsTable>>,
+    // records open snapshots still see in place of newer writes (see snapshot.rs)
+    snapshots: Snapshots<Record>,
+    archive: Option<Archive>,
+}
+
+impl LsmStorage {
+    // -> Open LSM storage rooted at the given database directory, whose writer lock the caller holds
+    pub fn open(base: impl AsRef<Path>, config: EngineConfig, cipher: Option<Arc<Cipher>>) -> Result<Self, DbError> {
+        let dir = base.as_ref().join("lsm");
+        let manifest_path = dir.join("manifest.json");
+
+        // a read-only open never creates or repairs anything
+        if config.read_only {
+            if !manifest_path.exists() {
+                return Err(DbError::DatabaseNotFound);
+            }
+        } else {
+            std::fs::create_dir_all(&dir)?;
+        }
+        let manifest = if manifest_path.exists() {
+            load_manifest::<LsmManifest>(&manifest_path, cipher.as_deref())?
+        } else {
+            let manifest = LsmManifest::default();
+            let data = serde_json::to_vec_pretty(&manifest)?;
+            write_file_atomic(&manifest_path, &seal_file(cipher.as_deref(), data, &manifest_path))?;
+            manifest
+        };
+
+        // tables are named by id, new ones are numbered on from the highest
+        let corrupted = || DbError::CorruptedManifest { path: manifest_path.display().to_string() };
+        let ids = manifest.tables.iter().map(|t| table_id(t).ok_or_else(corrupted)).collect::<Result<Vec<_>, _>>()?;
+        let next_table = ids.into_iter().max().unwrap_or(0);
+
+        // read-only, every table stays open, the writer can compact its file away under us
+        let capacity = if config.read_only { usize::MAX } else { config.max_open_files };
+        let handles = Arc::new(HandleCache::new(capacity));
+        let tables = manifest.tables
+            .iter()
+            .map(|name| {
+                SsTable::open(&dir.join(name), cipher.clone(), Arc::clone(&handles), config.read_only)
+                    .map(Arc::new)
+                    .map_err(|e| e.for_segment(name))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // a new archive starts from what the tables hold, older versions in them are lost
+        let archive = if config.archive && !config.read_only {
+            let mut archive = Archive::open(base.as_ref(), cipher.clone())?;
+            if archive.is_empty() && !tables.is_empty() {
+                let records = merge_tables(&pin(&tables)?, "")?;
+                archive.add_records(records.values(), "wal-000000.log")?;
+            }
+            Some(archive)
+        } else {
+            None
+        };
+
+        let wal = OpenOptions::new()
+            .read(true)
+            .append(!config.read_only)
+            .create(!config.read_only)
+            .open(dir.join(WAL))?;
+
+        let mut storage = Self {
+            dir,
+            manifest,
+            recovery: RecoveryReport::default(),
+            config,
+            compaction_stats: CompactionStats::default(),
+            compression_stats: CompressionStats::default(),
+            wal,
+            last_seq: 0,
+            memtable: BTreeMap::new(),
+            memtable_bytes: 0,
+            tables,
+            handles,
+            next_table,
+            cipher,
+            backups_running: 0,
+            retired: Vec::new(),
+            snapshots: Snapshots::default(),
+            archive,
+        };
+        storage.recovery = storage.replay_wal()?;
+        for table in storage.tables.iter().filter(|t| t.bloom_rebuilt) {
+            storage.recovery.rebuilt_indexes.push(table.name.clone());
+        }
+        Ok(storage)
+    }
+
+    // -> Rebuild the memtable from the write-ahead log, cutting off a torn record at its tail
+    fn replay_wal(&mut self) -> Result<RecoveryReport, DbError> {
+        let mut report = RecoveryReport::default();
+        self.last_seq = self.manifest.last_seq;
+        let mut buf = Vec::new();
+        self.wal.seek(SeekFrom::Start(0))?;
+        self.wal.read_to_end(&mut buf)?;
+
+        let mut pos = 0;
+        while pos < buf.len() {
+            let entry = Record::record_len(&buf[pos..])
+                .and_then(|len| Some((Record::decode_entry(buf.get(pos..pos + len)?, self.cipher.as_deref(), Location::new(WAL, pos as u64))?, len)));
+
+            // a batch cut short is dropped whole
+            match entry {
+                Some((records, len)) => {
+                    pos += len;
+                    for (_, record) in records {
+                        self.last_seq = self.last_seq.max(record.seq);
+                        self.insert_memtable(record);
+                    }
+                }
+                None if Record::is_torn_tail(&buf[pos..]) => break,
+                None => return Err(DbError::Corruption { segment: WAL.to_string(), offset: pos as u64 }),
+            }
+        }
+
+        // the log is only ever appended to, so an unreadable tail is a write cut short by a crash
+        // (or, read-only, one the writer is still making)
+        if pos < buf.len() && !self.config.read_only {
+            self.wal.set_len(pos as u64)?;
+            self.wal.sync_all()?;
+            report.truncated_bytes += (buf.len() - pos) as u64;
+        }
+
+        Ok(report)
+    }
+
+    fn insert_memtable(&mut self, record: Record) {
+        self.memtable_bytes += record.encoded_len() as u64;
+        self.memtable.insert(record.key.clone(), record);
+    }
+
+    pub fn save_manifest(&self) -> std::io::Result<()> {
+        let data = serde_json::to_vec_pretty(&self.manifest)?;
+        let path = self.dir.join("manifest.json");
+        write_file_atomic(&path, &seal_file(self.cipher.as_deref(), data, &path))
+    }
+
+    // -> Handle to the write-ahead log and the last write sequence it holds, for syncing outside the lock
+    pub fn sync_handle(&self) -> std::io::Result<(File, u64)> {
+        Ok((self.wal.try_clone()?, self.last_seq))
+    }
+
+    pub fn table_count(&self) -> usize {
+        self.tables.len()
+    }
+
+    fn next_table_name(&mut self) -> String {
+        self.next_table += 1;
+        table_name(self.next_table)
+    }
+
+    // -> Append a record to the write-ahead log and the memtable, returns the write sequence
+    pub fn append(&mut self, record: &Record) -> std::io::Result<u64> {
+        let record = Record { seq: self.last_seq + 1, ..record.clone() };
+        let at = Location::new(WAL, self.wal.metadata()?.len());
+        let record_bytes = record.encode(self.config.compression, self.cipher.as_deref(), at);
+        self.compression_stats.record(record.encoded_len(), record_bytes.len());
+        self.write_wal(&record_bytes)?;
+
+        self.apply(record)?;
+        self.maybe_flush()?;
+        Ok(self.last_seq)
+    }
+
+    // -> Append records to the write-ahead log as one batch entry, replaying it brings back
+    //    either all of them or none. Returns the write sequence of the last one.
+    pub fn append_batch(&mut self, records: &[Record]) -> std::io::Result<u64> {
+        if records.is_empty() {
+            return Ok(self.last_seq);
+        }
+
+        let records: Vec<Record> = records
+            .iter()
+            .zip(self.last_seq + 1..)
+            .map(|(record, seq)| Record { seq, ..record.clone() })
+            .collect();
+        let at = Location::new(WAL, self.wal.metadata()?.len());
+        let (bytes, _) = Record::encode_batch(&records, self.config.compression, self.cipher.as_deref(), at);
+        self.compression_stats.record(records.iter().map(|r| r.encoded_len()).sum(), bytes.len());
+        self.write_wal(&bytes)?;
+
+        // flushed only once the whole batch is in the memtable, the log it's cleared from holds all of it
+        for record in records {
+            self.apply(record)?;
+        }
+        self.maybe_flush()?;
+        Ok(self.last_seq)
+    }
+
+    fn write_wal(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        append(&mut self.wal, bytes, self.config.durability == Durability::FlushEveryWrite)?;
+        Ok(())
+    }
+
+    // -> Put a record just written to the log into the memtable
+    fn apply(&mut self, record: Record) -> std::io::Result<()> {
+        if self.snapshots.wants(&record.key) {
+            let prev = self.lookup(&record.key).map_err(|e| std::io::Error::other(format!("{:?}", e)))?;
+            self.snapshots.record(&record.key, record.seq, prev);
+        }
+
+        self.last_seq = record.seq;
+        self.insert_memtable(record);
+        Ok(())
+    }
+
+    fn maybe_flush(&mut self) -> std::io::Result<()> {
+        if self.memtable_bytes >= self.config.segment_size {
+            self.flush_memtable()?;
+        }
+        Ok(())
+    }
+
+    // -> Write the memtable out as a new table, then clear the log it was replayed from.
+    //    The table is in the manifest before the log is cleared, so a crash in between
+    //    only replays records that are already in the table.
+    fn flush_memtable(&mut self) -> std::io::Result<()> {
+        if self.memtable.is_empty() {
+            return Ok(());
+        }
+
+        let name = self.next_table_name();
+        let table = SsTable::write(&self.dir.join(&name), self.memtable.values(), self.config.compression, self.cipher.clone(), Arc::clone(&self.handles))?;
+        self.tables.push(Arc::new(table));
+        self.manifest.tables.push(name);
+        self.manifest.last_seq = self.last_seq;
+        self.save_manifest()?;
+
+        if let Some(archive) = &mut self.archive {
+            archive.add_copy(&self.dir.join(WAL), &format!("wal-{:06}.log", self.next_table))?;
+        }
+        self.wal.set_len(0)?;
+        self.wal.sync_all()?;
+        self.memtable.clear();
+        self.memtable_bytes = 0;
+
+        Ok(())
+    }
+
+    // -> Look up the live record of a key, the memtable and then tables from newest to oldest.
+    //    Only the memtable is read under the lock, tables through the handles taken with it.
+    pub fn get(storage: &Mutex<Self>, key: &str) -> Result<Option<Record>, DbError> {
+        let tables = {
+            let storage = storage.lock().unwrap();
+            if let Some(record) = storage.memtable.get(key) {
+                return Ok(Some(record.clone()).filter(|r| !r.deleted));
+            }
+            storage.candidates(key)?
+        };
+        newest(&tables, key)
+    }
+
+    // -> Same as `get`, for callers already holding the lock
+    fn lookup(&self, key: &str) -> Result<Option<Record>, DbError> {
+        if let Some(record) = self.memtable.get(key) {
+            return Ok(Some(record.clone()).filter(|r| !r.deleted));
+        }
+        newest(&self.candidates(key)?, key)
+    }
+
+    // -> Tables that may hold key with their handles, newest first
+    fn candidates(&self, key: &str) -> std::io::Result<Vec<(Arc<SsTable>, Arc<Handle>)>> {
+        let tables: Vec<Arc<SsTable>> = self.tables.iter().rev().filter(|t| t.may_contain(key)).cloned().collect();
+        pin(&tables)
+    }
+
+    // -> Records of the memtable whose key starts with prefix
+    fn memtable_prefix(&self, prefix: &str) -> Vec<Record> {
+        self.memtable
+            .range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(_, record)| record.clone())
+            .collect()
+    }
+
+    // -> Live records whose key starts with prefix, ordered by key. The tables are merged outside the lock.
+    pub fn scan_prefix(storage: &Mutex<Self>, prefix: &str) -> Result<Vec<Record>, DbError> {
+        let (tables, memtable) = {
+            let storage = storage.lock().unwrap();
+            (pin(&storage.tables)?, storage.memtable_prefix(prefix))
+        };
+
+        let mut records = merge_tables(&tables, prefix)?;
+        for record in memtable {
+            records.insert(record.key.clone(), record);
+        }
+        Ok(records.into_values().filter(|r| !r.deleted).collect())
+    }
+
+    // -> Pin the current state for snapshot reads, returns the write sequence it's at
+    pub fn snapshot(&mut self) -> u64 {
+        self.snapshots.open(self.last_seq);
+        self.last_seq
+    }
+
+    // -> Whether key was written after the open snapshot at seq
+    pub fn written_since(&self, key: &str, seq: u64) -> bool {
+        self.snapshots.written_since(key, seq)
+    }
+
+    pub fn release_snapshot(&mut self, seq: u64) {
+        self.snapshots.release(seq);
+        self.drop_retired();
+    }
+
+    // -> Record of a key as snapshot seq sees it. Only the memtable is read under the lock,
+    //    tables never change and compaction keeps the ones it replaces while snapshots are open.
+    pub fn get_at(storage: &Mutex<Self>, key: &str, seq: u64) -> Result<Option<Record>, DbError> {
+        let tables = {
+            let storage = storage.lock().unwrap();
+            if let Some(state) = storage.snapshots.resolve(key, seq) {
+                return Ok(state.cloned());
+            }
+            if let Some(record) = storage.memtable.get(key) {
+                return Ok(Some(record.clone()).filter(|r| !r.deleted));
+            }
+            storage.candidates(key)?
+        };
+        newest(&tables, key)
+    }
+
+    // -> Records whose key starts with prefix as snapshot seq sees them, ordered by key
+    pub fn scan_prefix_at(storage: &Mutex<Self>, prefix: &str, seq: u64) -> Result<Vec<Record>, DbError> {
+        let (tables, memtable, changed) = {
+            let storage = storage.lock().unwrap();
+            (pin(&storage.tables)?, storage.memtable_prefix(prefix), storage.snapshots.changed(prefix, seq))
+        };
+
+        let mut records = merge_tables(&tables, prefix)?;
+        for record in memtable {
+            records.insert(record.key.clone(), record);
+        }
+        records.retain(|_, r| !r.deleted);
+        overlay(&mut records, changed);
+        Ok(records.into_values().collect())
+    }
+
+    // -> Delete the tables compaction replaced once no backup or snapshot can still read them
+    fn drop_retired(&mut self) {
+        if self.backups_running == 0 && self.snapshots.is_empty() {
+            for table in self.retired.drain(..) {
+                table.remove_files();
+            }
+        }
+    }
+
+    // -> Merge every table into one, only holding the lock to pick the tables and to swap the result in.
+    //    Tables flushed while the merge runs are newer than all of its input and are left alone.
+    pub fn compact(storage: &Mutex<Self>) -> Result<(), DbError> {
+        let started = Instant::now();
+
+        let (job, mut blocked) = {
+            let mut storage = storage.lock().unwrap();
+            let locked = Instant::now();
+            let job = if storage.tables.len() < 2 {
+                None
+            } else {
+                let name = storage.next_table_name();
+                Some((storage.tables.clone(), storage.dir.join(name), storage.config.compression, storage.cipher.clone(), Arc::clone(&storage.handles)))
+            };
+            (job, locked.elapsed())
+        };
+        let (tables, path, compression, cipher, handles) = match job {
+            Some(job) => job,
+            None => return Ok(()),
+        };
+
+        // the oldest table takes part, so tombstones (and expired records) have nothing left to shadow
+        let records = merge_tables(&pin(&tables)?, "")?;
+        let now = now_secs();
+        let merged = SsTable::write(&path, records.values().filter(|r| !r.deleted && !r.is_expired(now)), compression, cipher, handles)?;
+
+        let mut storage = storage.lock().unwrap();
+        let locked = Instant::now();
+
+        let removed: Vec<String> = tables.iter().map(|t| t.name.clone()).collect();
+        storage.manifest.tables.retain(|t| !removed.contains(t));
+        storage.manifest.tables.insert(0, merged.name.clone());
+        storage.manifest.last_compaction = Some(Utc::now().to_string());
+        storage.save_manifest()?;
+
+        storage.tables.retain(|t| !removed.contains(&t.name));
+        storage.tables.insert(0, Arc::new(merged));
+        for table in tables.iter() {
+            storage.retired.push(table.clone());
+        }
+        storage.drop_retired();
+
+        blocked += locked.elapsed();
+        storage.compaction_stats.record(tables.len(), started.elapsed(), blocked);
+        Ok(())
+    }
+
+    // -> Copy a consistent snapshot of the database into dest while reads and writes carry on.
+    //    The lock is only held to pin the current tables and take a copy of the memtable.
+    pub fn backup(storage: &Mutex<Self>, dest: &Path) -> Result<(), DbError> {
+        let job = storage.lock().unwrap().plan_backup();
+        let result = job.run(dest);
+        storage.lock().unwrap().finish_backup();
+        result
+    }
+
+    fn plan_backup(&mut self) -> BackupJob {
+        self.backups_running += 1;
+
+        // the memtable written out as a log of its own, replaying it restores the memtable
+        let mut wal = Vec::new();
+        for record in self.memtable.values() {
+            let at = Location::new(WAL, wal.len() as u64);
+            wal.extend_from_slice(&record.encode(self.config.compression, self.cipher.as_deref(), at));
+        }
+
+        BackupJob { tables: self.tables.clone(), manifest: self.manifest.clone(), wal, cipher: self.cipher.clone() }
+    }
+
+    fn finish_backup(&mut self) {
+        self.backups_running -= 1;
+        self.drop_retired();
+    }
+
+    // -> Live records as they were at `until` (unix seconds), replayed from the archive and the write-ahead log.
+    //    Only the log is read under the lock, a flush could clear it otherwise.
+    pub fn records_at(storage: &Mutex<Self>, until: u64) -> Result<Vec<Record>, DbError> {
+        let (archive, wal) = {
+            let storage = storage.lock().unwrap();
+            let archive = storage.archive.clone().ok_or(DbError::ArchiveDisabled)?;
+            (archive, std::fs::read(storage.dir.join(WAL))?)
+        };
+
+        let mut state = BTreeMap::new();
+        archive.replay(until, &mut state)?;
+        replay_records(&wal, WAL, archive.cipher(), until, &mut state)?;
+        Ok(state.into_values().filter(|r| !r.deleted).collect())
+    }
+
+    // -> Check every table and the write-ahead log of the database under base against their checksums.
+    //    Used to validate a backup before it's restored.
+    pub fn verify(base: &Path, cipher: Option<Arc<Cipher>>) -> Result<(), DbError> {
+        let dir = base.join("lsm");
+        let manifest: LsmManifest = load_manifest(&dir.join("manifest.json"), cipher.as_deref())?;
+
+        let handles = Arc::new(HandleCache::new(1));
+        for name in &manifest.tables {
+            let table = SsTable::open(&dir.join(name), cipher.clone(), Arc::clone(&handles), false).map_err(|e| e.for_segment(name))?;
+            let file = table.file()?;
+            table.scan_prefix(&file, "")?;
+        }
+
+        let wal = std::fs::read(dir.join(WAL))?;
+        let mut pos = 0;
+        while pos < wal.len() {
+            let len = Record::record_len(&wal[pos..])
+                .filter(|len| wal.get(pos..pos + len).and_then(|b| Record::decode_entry(b, cipher.as_deref(), Location::new(WAL, pos as u64))).is_some());
+            match len {
+                Some(len) => pos += len,
+                None => return Err(DbError::Corruption { segment: WAL.to_string(), offset: pos as u64 }),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// -> Tables pinned for a backup, built by `LsmStorage::plan_backup`
+struct BackupJob {
+    tables: Vec<Arc<SsTable>>,
+    manifest: LsmManifest,
+    // encoded records of the memtable
+    wal: Vec<u8>,
+    cipher: Option<Arc<Cipher>>,
+}
+
+impl BackupJob {
+    // -> Copy the pinned tables, the manifest goes last so an interrupted backup can't be opened
+    fn run(&self, dest: &Path) -> Result<(), DbError> {
+        let dir = dest.join("lsm");
+        std::fs::create_dir_all(&dir)?;
+
+        for table in &self.tables {
+            table.copy_to(&dir)?;
+        }
+        write_file_atomic(&dir.join(WAL), &self.wal)?;
+
+        let data = serde_json::to_vec_pretty(&self.manifest)?;
+        let path = dir.join("manifest.json");
+        write_file_atomic(&path, &seal_file(self.cipher.as_deref(), data, &path))?;
+        Ok(())
+    }
+}
+
+// -> Tables with a handle to read each through. Taken under the lock, compaction can delete
+//    a table's file once it's released but a handle still reads it.
+fn pin(tables: &[Arc<SsTable>]) -> std::io::Result<Vec<(Arc<SsTable>, Arc<Handle>)>> {
+    tables.iter().map(|table| Ok((Arc::clone(table), table.file()?))).collect()
+}
+
+// -> Live record of key in the first of the tables that holds it, tables given newest first
+fn newest(tables: &[(Arc<SsTable>, Arc<Handle>)], key: &str) -> Result<Option<Record>, DbError> {
+    for (table, file) in tables {
+        if let Some(record) = table.get(file, key)? {
+            return Ok(Some(record).filter(|r| !r.deleted));
+        }
+    }
+    Ok(None)
+}
+
+// -> Latest record of every key with the prefix across tables given oldest first, tombstones included
+fn merge_tables(tables: &[(Arc<SsTable>, Arc<Handle>)], prefix: &str) -> Result<BTreeMap<String, Record>, DbError> {
+    let mut records = BTreeMap::new();
+    for (table, file) in tables {
+        for record in table.scan_prefix(file, prefix)? {
+            records.insert(record.key.clone(), record);
+        }
+    }
+    Ok(records)
+}
@@ -1,62 +1,24 @@
-use std::{sync::{Arc, Mutex}, time};
-
-use api::Enso;
-use engine::Engine;
-use rustyline::{error::ReadlineError, DefaultEditor, Editor};
-use sql::{lexer::{Lexer, Token}, parser::Parser};
+use enso::Enso;
+use repl::start_repl;
 use tcp::start_tcp;
-use types::{Column, Value};
-use repl::{start_repl};
 
-mod record;
-mod storage;
-mod utils;
-mod engine;
-mod types;
-mod api;
-mod schema;
-mod error;
-mod codec;
-mod sql;
+mod pretty;
 mod repl;
-mod client;
 mod tcp;
-mod pretty;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // let mut db = Enso::open("test_db").unwrap();
-    //
-    // db.create_table(
-    //     "users",
-    //     schema! {
-    //         id: Int => pk,
-    //         name: String,
-    //         age: Int,
-    //     }
-    // ).unwrap();
-    //
-    // db.insert(row![1, "amartya", 24]).unwrap();
-    //
-    // let row = db.select_by_pk(1).unwrap().unwrap();
-    // println!("Row: {:?}", row);
-    //
-    // db.delete_by_pk(1).unwrap();
-    // println!("Row after deletion: {:?}", db.select_by_pk(1).unwrap());
-
-    // TESTING parser ///////////////////////////////////////////////////////
-
-    // let mut lexer = Lexer::new(r#"INSERT INTO users VALUES (1, "amartya");"#);
-    //
-    // let mut parser = Parser::new(lexer).unwrap();
-    // let stmt = parser.parse_stmt().unwrap();
-    //
-    // println!("{:#?}", stmt);
-
-    // CREATING interactive repl ///////////////////////////////////////////
-
-    let db = Enso::open("test_db").unwrap();
-    let db = Arc::new(Mutex::new(db));
-
+    let db = match Enso::open("test_db") {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("[enso] Failed to open database: {:?}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(report) = db.recovery_report().filter(|r| !r.is_clean()) {
+        println!("[enso] Recovered from unclean shutdown: {:?}", report);
+    }
+
+    // the REPL and every TCP client get their own session on the same engine
     start_tcp(db.clone());
     start_repl(db);
 
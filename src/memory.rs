@@ -0,0 +1,126 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+use crate::{backend::StorageBackend, durability::Durability, error::DbError, record::Record, snapshot::{overlay, Snapshots}, utils::now_secs};
+
+#[derive(Default)]
+struct MemoryState {
+    // live records only, a delete removes the key
+    records: BTreeMap<String, Record>,
+    last_seq: u64,
+    snapshots: Snapshots<Record>,
+}
+
+impl MemoryState {
+    // -> Apply records in order, returns the write sequence of the last one
+    fn append(&mut self, records: &[Record]) -> u64 {
+        for record in records {
+            let record = Record { seq: self.last_seq + 1, ..record.clone() };
+            if self.snapshots.wants(&record.key) {
+                let prev = self.records.get(&record.key).cloned();
+                self.snapshots.record(&record.key, record.seq, prev);
+            }
+
+            self.last_seq = record.seq;
+            if record.deleted {
+                self.records.remove(&record.key);
+            } else {
+                self.records.insert(record.key.clone(), record);
+            }
+        }
+
+        self.last_seq
+    }
+}
+
+// -> Backend keeping everything in memory, nothing touches the filesystem and
+//    everything is gone once the engine is dropped. Meant for tests and caches.
+#[derive(Default)]
+pub struct MemoryBackend {
+    state: Mutex<MemoryState>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StorageBackend for MemoryBackend {
+    fn append(&self, record: &Record) -> std::io::Result<u64> {
+        self.append_batch(std::slice::from_ref(record))
+    }
+
+    fn append_batch(&self, records: &[Record]) -> std::io::Result<u64> {
+        Ok(self.state.lock().unwrap().append(records))
+    }
+
+    fn append_batch_since(&self, records: &[Record], seq: u64) -> Result<u64, DbError> {
+        let mut state = self.state.lock().unwrap();
+        if records.iter().any(|record| state.snapshots.written_since(&record.key, seq)) {
+            return Err(DbError::TransactionConflict);
+        }
+        Ok(state.append(records))
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Record>, DbError> {
+        let state = self.state.lock().unwrap();
+        Ok(state.records.get(key).cloned())
+    }
+
+    fn scan_prefix(&self, prefix: &str) -> Result<Vec<Record>, DbError> {
+        let state = self.state.lock().unwrap();
+        Ok(state.records
+            .range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(_, record)| record.clone())
+            .collect())
+    }
+
+    fn snapshot(&self) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        let seq = state.last_seq;
+        state.snapshots.open(seq);
+        seq
+    }
+
+    fn release_snapshot(&self, seq: u64) {
+        self.state.lock().unwrap().snapshots.release(seq);
+    }
+
+    fn get_at(&self, key: &str, seq: u64) -> Result<Option<Record>, DbError> {
+        let state = self.state.lock().unwrap();
+        Ok(match state.snapshots.resolve(key, seq) {
+            Some(record) => record.cloned(),
+            None => state.records.get(key).cloned(),
+        })
+    }
+
+    fn scan_prefix_at(&self, prefix: &str, seq: u64) -> Result<Vec<Record>, DbError> {
+        let state = self.state.lock().unwrap();
+        let mut records: BTreeMap<String, Record> = state.records
+            .range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, record)| (key.clone(), record.clone()))
+            .collect();
+        overlay(&mut records, state.snapshots.changed(prefix, seq));
+        Ok(records.into_values().collect())
+    }
+
+    // nothing to persist
+    fn sync(&self) -> std::io::Result<u64> {
+        Ok(self.state.lock().unwrap().last_seq)
+    }
+
+    fn set_durability(&self, _durability: Durability) {}
+
+    fn segment_count(&self) -> usize {
+        1
+    }
+
+    // -> Drop expired records
+    fn compact(&self) -> Result<(), DbError> {
+        let now = now_secs();
+        self.state.lock().unwrap().records.retain(|_, record| !record.is_expired(now));
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+use std::path::{Path, PathBuf};
+
+use crate::{batch::WriteBatch, config::EngineConfig, engine::Engine, error::DbError, schema::SchemaManager, types::TableSchema};
+
+// Databases used to share one store under the data directory:
+// <data>/manifest.json, segments/, index/   records of every database, keyed `<db>:<table>:<pk>`
+// <data>/schema/<db>/<table>.json            table schemas
+// Each database now owns `<data>/<db>/` (see api.rs). One still in the shared store is copied into
+// its own directory the first time it's opened for writing. Its schema directory goes last, so a copy
+// cut short is simply made again on the next open. The shared store itself is only ever read.
+
+// -> Schema directory of db in the shared store
+fn shared_schema_dir(base: &Path, db: &str) -> PathBuf {
+    base.join("schema").join(db)
+}
+
+// -> Whether db still lives in the shared store under base
+pub fn is_shared(base: &Path, db: &str) -> bool {
+    base.join("manifest.json").is_file() && shared_schema_dir(base, db).is_dir()
+}
+
+// -> Copy the rows and table schemas of db from the shared store under base into its own engine and schemas
+pub fn from_shared(base: &Path, db: &str, engine: &Engine, schema: &mut SchemaManager) -> Result<(), DbError> {
+    let shared = Engine::open_with_config(base, EngineConfig::default().read_only(true))?;
+    let prefix = format!("{}:", db);
+    let mut batch = WriteBatch::new();
+    for (key, value) in shared.scan_prefix(&prefix)? {
+        batch.put(key[prefix.len()..].to_string(), value);
+    }
+    engine.write(batch)?;
+
+    let dir = shared_schema_dir(base, db);
+    for entry in std::fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|s| s.to_str()) == Some("json") {
+            let table: TableSchema = serde_json::from_slice(&std::fs::read(&path)?)?;
+            let name = table.name.clone();
+            schema.insert(db, &name, table)?;
+        }
+    }
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
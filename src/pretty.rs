@@ -1,4 +1,4 @@
-use crate::types::{TableSchema, Value};
+use enso::{TableSchema, Value};
 
 fn separator(widths: &[usize]) -> String {
     widths
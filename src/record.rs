@@ -1,45 +1,300 @@
-use crate::utils::{decode_u32, encode_u32};
+use std::borrow::Cow;
 
+use crate::{compression::{decompress, Compression}, crypto::Cipher, utils::{decode_u32, encode_u32}};
+
+// On-disk record layout (big endian):
+// [version: u8][crc32: u32][codec: u8][key_len: u32][val_len: u32][timestamp: u64][deleted: u8][ttl: u64][seq: u64][key][value]
+// val_len is the length of the value as stored, i.e. after compression with the codec (see compression.rs).
+// ttl is in seconds from timestamp, 0 for records that never expire.
+// seq is the write sequence the storage assigned the record, increasing across the whole database.
+// The checksum covers everything after the crc field.
+//
+// Records of the original format, which had neither a version byte nor a checksum, are still read:
+// [key_len: u32][val_len: u32][timestamp: u64][deleted: u8][key][value]
+// Its first byte is the top byte of key_len, 0 for any key under 16 MiB, so it can't be taken for
+// a version. Its keys are never empty, which keeps zeros left at the end of a log from reading as records.
+//
+// Encrypted databases wrap every record in a sealed frame (see crypto.rs), the AEAD tag
+// takes the place of the checksum:
+// [version: 3][len: u32][sealed record of the layout above]
+// The seal is bound to where the frame is stored (file name and offset, see `Location`) and, inside a
+// batch, to the batch's record count, so sealed records can't be moved, swapped or dropped from a batch.
+//
+// Records committed together (a transaction) are written as one batch entry, so recovery replays
+// all of them or, if the batch was cut short by a crash, none:
+// [version: 6][crc32: u32][len: u32][count: u32][records, each encoded as above]
+// The checksum covers everything after the crc field.
+pub const FORMAT_VERSION: u8 = 5;
+const ENCRYPTED_VERSION: u8 = 3;
+const ENCRYPTED_HEADER_LEN: usize = 5;
+const RECORD_AAD: &[u8] = b"enso record";
+const BATCH_VERSION: u8 = 6;
+const BATCH_HEADER_LEN: usize = 13;
+// header length of the current version, also enough bytes to tell the length of a record of any version
+pub const HEADER_LEN: usize = 39;
+const LEGACY_HEADER_LEN: usize = 17;
+
+// -> Where an entry is stored: the name of its log or table file and its offset there
+#[derive(Clone, Copy)]
+pub struct Location<'a> {
+    pub file: &'a str,
+    pub offset: u64,
+}
+
+impl<'a> Location<'a> {
+    pub fn new(file: &'a str, offset: u64) -> Self {
+        Self { file, offset }
+    }
+
+    // -> Location pos bytes further into the same file
+    fn at(self, pos: usize) -> Self {
+        Self { offset: self.offset + pos as u64, ..self }
+    }
+
+    // -> Additional data a record stored here is sealed with, count is that of its batch (0 outside one)
+    fn aad(self, count: u32) -> Vec<u8> {
+        let mut aad = RECORD_AAD.to_vec();
+        aad.extend_from_slice(&encode_u32(self.file.len() as u32));
+        aad.extend_from_slice(self.file.as_bytes());
+        aad.extend_from_slice(&self.offset.to_be_bytes());
+        aad.extend_from_slice(&encode_u32(count));
+        aad
+    }
+}
+
+#[derive(Clone)]
 pub struct Record {
     pub key: String,
     pub value: Vec<u8>,
     pub timestamp: u64,
     pub deleted: bool,
+    // seconds after timestamp the record expires, 0 never
+    pub ttl: u64,
+    // write sequence, set by the storage when the record is appended
+    pub seq: u64,
 }
 
 impl Record {
     pub fn new(key: String, value: Vec<u8>, timestamp: u64, deleted: bool) -> Self {
-        Self { key, value, timestamp, deleted }
+        Self { key, value, timestamp, deleted, ttl: 0, seq: 0 }
+    }
+
+    pub fn with_ttl(mut self, ttl: u64) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    // -> Whether this record was written after other, for the same key. Records from before write
+    //    sequences were stored all have 0 and are told apart by timestamp, a tie goes to this one.
+    pub fn supersedes(&self, other: &Record) -> bool {
+        (self.seq, self.timestamp) >= (other.seq, other.timestamp)
+    }
+
+    // -> Whether the record's ttl has run out at the given unix time (seconds)
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.ttl != 0 && self.timestamp.saturating_add(self.ttl) <= now
     }
 
     pub fn serialize(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
+        self.serialize_with(Compression::None)
+    }
+
+    // -> Serialize with compression, then seal the record when a cipher is given.
+    //    at is where the record is going to be stored, a sealed record only decodes from there.
+    pub fn encode(&self, compression: Compression, cipher: Option<&Cipher>, at: Location) -> Vec<u8> {
+        self.encode_in(compression, cipher, at, 0)
+    }
+
+    fn encode_in(&self, compression: Compression, cipher: Option<&Cipher>, at: Location, count: u32) -> Vec<u8> {
+        let bytes = self.serialize_with(compression);
+        let Some(cipher) = cipher else {
+            return bytes;
+        };
+
+        let sealed = cipher.seal(&bytes, &at.aad(count));
+        let mut frame = Vec::with_capacity(ENCRYPTED_HEADER_LEN + sealed.len());
+        frame.push(ENCRYPTED_VERSION);
+        frame.extend_from_slice(&encode_u32(sealed.len() as u32));
+        frame.extend_from_slice(&sealed);
+        frame
+    }
+
+    // -> Encode records as one batch entry to be stored at at, also returns where each record starts within it
+    pub fn encode_batch(records: &[Record], compression: Compression, cipher: Option<&Cipher>, at: Location) -> (Vec<u8>, Vec<usize>) {
+        let count = records.len() as u32;
+        let mut bytes = vec![BATCH_VERSION, 0, 0, 0, 0, 0, 0, 0, 0];
+        bytes.extend_from_slice(&encode_u32(count));
+        let mut offsets = Vec::with_capacity(records.len());
+        for record in records {
+            offsets.push(bytes.len());
+            bytes.extend_from_slice(&record.encode_in(compression, cipher, at.at(bytes.len()), count));
+        }
+
+        let len = (bytes.len() - BATCH_HEADER_LEN) as u32;
+        bytes[5..9].copy_from_slice(&encode_u32(len));
+        let crc = crc32fast::hash(&bytes[5..]);
+        bytes[1..5].copy_from_slice(&encode_u32(crc));
+        (bytes, offsets)
+    }
+
+    // -> Decode the entry stored at at, starting at the beginning of buf: a single record or every record
+    //    of a batch, each with where it starts within buf. None if any of it is truncated or fails its check.
+    pub fn decode_entry(buf: &[u8], cipher: Option<&Cipher>, at: Location) -> Option<Vec<(usize, Record)>> {
+        if buf.first() != Some(&BATCH_VERSION) {
+            return Some(vec![(0, Self::decode(buf, cipher, at)?)]);
+        }
+
+        let batch = buf.get(..Self::record_len(buf)?)?;
+        if batch.len() < BATCH_HEADER_LEN || crc32fast::hash(&batch[5..]) != decode_u32(&batch[1..5]) {
+            return None;
+        }
+
+        let count = decode_u32(&batch[9..13]);
+        let mut records = Vec::new();
+        let mut pos = BATCH_HEADER_LEN;
+        while pos < batch.len() {
+            let record_len = Self::record_len(&batch[pos..])?;
+            records.push((pos, Self::decode_in(batch.get(pos..pos + record_len)?, cipher, at.at(pos), count)?));
+            pos += record_len;
+        }
+        (records.len() == count as usize).then_some(records)
+    }
+
+    // -> Decode a record written by `encode` to at. With a cipher only sealed records are accepted,
+    //    so plaintext can't be slipped into an encrypted database.
+    pub fn decode(buf: &[u8], cipher: Option<&Cipher>, at: Location) -> Option<Self> {
+        Self::decode_in(buf, cipher, at, 0)
+    }
+
+    fn decode_in(buf: &[u8], cipher: Option<&Cipher>, at: Location, count: u32) -> Option<Self> {
+        let Some(cipher) = cipher else {
+            return Self::deserialize(buf);
+        };
+
+        let record_len = Self::record_len(buf)?;
+        if buf[0] != ENCRYPTED_VERSION || buf.len() < record_len {
+            return None;
+        }
+
+        let bytes = cipher.open(&buf[ENCRYPTED_HEADER_LEN..record_len], &at.aad(count))?;
+        Self::deserialize(&bytes)
+    }
+
+    // -> Serialize with the value compressed, the value is kept as is when compressing doesn't make it smaller
+    pub fn serialize_with(&self, compression: Compression) -> Vec<u8> {
+        let (codec, value) = match compression.compress(&self.value) {
+            Some(compressed) if compressed.len() < self.value.len() => (compression.id(), Cow::Owned(compressed)),
+            _ => (Compression::None.id(), Cow::Borrowed(&self.value)),
+        };
 
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.key.len() + value.len());
+
+        bytes.push(FORMAT_VERSION);
+        bytes.extend_from_slice(&[0u8; 4]);
+        bytes.push(codec);
         bytes.extend_from_slice(&encode_u32(self.key.len() as u32));
-        bytes.extend_from_slice(&encode_u32(self.value.len() as u32));
+        bytes.extend_from_slice(&encode_u32(value.len() as u32));
         bytes.extend_from_slice(&self.timestamp.to_be_bytes());
         bytes.push(self.deleted as u8);
+        bytes.extend_from_slice(&self.ttl.to_be_bytes());
+        bytes.extend_from_slice(&self.seq.to_be_bytes());
         bytes.extend_from_slice(self.key.as_bytes());
-        bytes.extend_from_slice(&self.value);
+        bytes.extend_from_slice(&value);
+
+        // fill in checksum
+        let crc = crc32fast::hash(&bytes[5..]);
+        bytes[1..5].copy_from_slice(&encode_u32(crc));
 
         bytes
     }
 
-    pub fn deserialize(buf: &[u8]) -> Self {
-        let key_len = decode_u32(&buf[0..4]) as usize;
-        let val_len = decode_u32(&buf[4..8]) as usize;
-        let timestamp = u64::from_be_bytes(buf[8..16].try_into().unwrap());
-        let deleted = buf[16] != 0;
+    // -> Length of the record once serialized without compression
+    pub fn encoded_len(&self) -> usize {
+        HEADER_LEN + self.key.len() + self.value.len()
+    }
+
+    // -> Header length and offset of key_len for a format version
+    fn layout(version: u8) -> Option<(usize, usize)> {
+        match version {
+            // the original format, see above
+            0 => Some((LEGACY_HEADER_LEN, 0)),
+            FORMAT_VERSION => Some((HEADER_LEN, 6)),
+            _ => None,
+        }
+    }
+
+    // -> Total stored length of the record (or batch) starting at the beginning of buf (only its header is needed),
+    //    None if the header is not valid
+    pub fn record_len(buf: &[u8]) -> Option<usize> {
+        if buf.first() == Some(&ENCRYPTED_VERSION) {
+            let len = buf.get(1..ENCRYPTED_HEADER_LEN)?;
+            return Some(ENCRYPTED_HEADER_LEN + decode_u32(len) as usize);
+        }
+        if buf.first() == Some(&BATCH_VERSION) {
+            let len = buf.get(5..9)?;
+            return Some(BATCH_HEADER_LEN + decode_u32(len) as usize);
+        }
+
+        let (header_len, at) = Self::layout(*buf.first()?)?;
+        if buf.len() < header_len {
+            return None;
+        }
+
+        let key_len = decode_u32(&buf[at..at + 4]) as usize;
+        let val_len = decode_u32(&buf[at + 4..at + 8]) as usize;
+        if buf[0] == 0 && key_len == 0 {
+            return None;
+        }
+        Some(header_len + key_len + val_len)
+    }
+
+    // -> Whether an entry that doesn't decode is a write cut short by a crash, tail being everything
+    //    from it to the end of its log. That's the case when the tail is too short for a header, the
+    //    header's length runs past the end, or only zeros follow (a crash can leave the file extended
+    //    without its data). An entry that ends exactly at the end is all there and failed its check,
+    //    that and anything else still having data after it is corruption.
+    pub fn is_torn_tail(tail: &[u8]) -> bool {
+        let rest = match Self::record_len(tail) {
+            Some(len) if len > tail.len() => return true,
+            Some(len) if len == tail.len() => return false,
+            Some(len) => &tail[len..],
+            None if tail.len() < HEADER_LEN => return true,
+            None => tail,
+        };
+        rest.iter().all(|&b| b == 0)
+    }
+
+    // -> Decode a full record, None if it's truncated or fails the checksum
+    pub fn deserialize(buf: &[u8]) -> Option<Self> {
+        let (header_len, at) = Self::layout(*buf.first()?)?;
+        let record_len = Self::record_len(buf)?;
+        if buf.len() < record_len {
+            return None;
+        }
+
+        // the original format has no checksum
+        let legacy = buf[0] == 0;
+        if !legacy && crc32fast::hash(&buf[5..record_len]) != decode_u32(&buf[1..5]) {
+            return None;
+        }
+
+        let codec = if legacy { Compression::None.id() } else { buf[5] };
+        let key_len = decode_u32(&buf[at..at + 4]) as usize;
+        let timestamp = u64::from_be_bytes(buf[at + 8..at + 16].try_into().unwrap());
+        let deleted = buf[at + 16] != 0;
+        let field = |from: usize| u64::from_be_bytes(buf[at + from..at + from + 8].try_into().unwrap());
+        let (ttl, seq) = if legacy { (0, 0) } else { (field(17), field(25)) };
 
-        let key_start = 17;
+        let key_start = header_len;
         let key_end = key_start + key_len;
-        let val_end = key_end + val_len;
 
-        Record {
-            key: String::from_utf8(buf[key_start..key_end].to_vec()).unwrap(),
-            value: buf[key_end..val_end].to_vec(),
+        Some(Record {
+            key: String::from_utf8(buf[key_start..key_end].to_vec()).ok()?,
+            value: decompress(codec, &buf[key_end..record_len])?,
             timestamp,
             deleted,
-        }
+            ttl,
+            seq,
+        })
     }
 }
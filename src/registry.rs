@@ -0,0 +1,128 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, sync::{Arc, Condvar, LazyLock, Mutex, RwLock, Weak}};
+
+use crate::{config::EngineConfig, crypto, engine::Engine, error::DbError, migrate, schema::SchemaManager};
+
+// Databases open in this process. Every `Enso` session that opens a database gets the one already
+// open if there is one, so each directory has a single engine (storage, lock, caches) and a single
+// set of table schemas however many sessions use it. A database is closed with its last session.
+
+// -> Engine and table schemas of an open database, shared by its sessions
+pub(crate) struct Database {
+    pub engine: Engine,
+    // shared so a table one session creates is visible to the others
+    pub schema: RwLock<SchemaManager>,
+    // dropped after the engine, so the database stays registered until its lock is released
+    _entry: Option<Entry>,
+}
+
+// keyed by database directory, and whether it's opened read-only (that engine doesn't take the lock)
+type Key = (PathBuf, bool);
+type Registry = HashMap<Key, Slot>;
+
+// -> State of a database in the registry
+enum Slot {
+    // a session is opening it, others wait for it instead of racing for its lock
+    Opening,
+    // open, or closing once no session holds it any more
+    Open(Weak<Database>),
+}
+
+static DATABASES: LazyLock<Mutex<Registry>> = LazyLock::new(|| Mutex::new(Registry::new()));
+// notified whenever a database has finished opening (or failed to) or has been closed
+static CHANGED: Condvar = Condvar::new();
+
+// -> Registration of an open database, removed once the database is closed
+struct Entry(Key);
+
+impl Drop for Entry {
+    fn drop(&mut self) {
+        DATABASES.lock().unwrap().remove(&self.0);
+        CHANGED.notify_all();
+    }
+}
+
+impl Database {
+    // -> Database db under base, shared with the sessions that have it open already.
+    //    If it's open already, the open engine's settings apply and config must agree with them on
+    //    the backend, archive and encryption key.
+    pub fn open(base: &Path, db: &str, config: EngineConfig) -> Result<Arc<Self>, DbError> {
+        let path = std::path::absolute(base.join(db))?;
+        let key = (path, config.read_only);
+
+        // only held to look at the registry: opening (recovery, key derivation) and dropping a database
+        // happen without it, and the last reference of one may go at any point outside it.
+        // A database whose last session is being dropped stays registered until its engine has closed,
+        // opening it again before then would find it still locked.
+        let mut databases = DATABASES.lock().unwrap();
+        loop {
+            let open = match databases.get(&key) {
+                None => break,
+                Some(Slot::Opening) => None,
+                Some(Slot::Open(database)) => database.upgrade(),
+            };
+            if let Some(database) = open {
+                drop(databases);
+                database.check_config(&key.0, &config)?;
+                return Ok(database);
+            }
+            databases = CHANGED.wait(databases).unwrap();
+        }
+        databases.insert(key.clone(), Slot::Opening);
+        drop(databases);
+
+        let opened = Self::open_new(base, db, config, &key);
+        let mut databases = DATABASES.lock().unwrap();
+        match &opened {
+            Ok(database) => databases.insert(key, Slot::Open(Arc::downgrade(database))),
+            Err(_) => databases.remove(&key),
+        };
+        drop(databases);
+        CHANGED.notify_all();
+        opened
+    }
+
+    // -> Open the engine and schemas of a database nobody has open
+    fn open_new(base: &Path, db: &str, config: EngineConfig, key: &Key) -> Result<Arc<Self>, DbError> {
+        // a database from before each had its own directory is moved into it first
+        let shared = migrate::is_shared(base, db);
+        if shared && config.read_only {
+            return Err(DbError::NotMigrated { db: db.to_string() });
+        }
+
+        let engine = Engine::open_with_config(&key.0, config)?;
+        let mut schema = SchemaManager::new(base).with_cipher(engine.cipher());
+        schema.load_db(db, key.1)?;
+        if shared {
+            migrate::from_shared(base, db, &engine, &mut schema)?;
+        }
+
+        Ok(Arc::new(Self { engine, schema: RwLock::new(schema), _entry: Some(Entry(key.clone())) }))
+    }
+
+    // -> Database kept in memory, not shared with other opens (only with clones of its sessions)
+    pub fn in_memory(db: &str) -> Result<Arc<Self>, DbError> {
+        let engine = Engine::in_memory(EngineConfig::default());
+        let mut schema = SchemaManager::in_memory();
+        schema.load_db(db, false)?;
+
+        Ok(Arc::new(Self { engine, schema: RwLock::new(schema), _entry: None }))
+    }
+
+    // -> Fails if a session's config disagrees with the one the database was opened with
+    //    on how it's stored, or gives another encryption key than the one it was unlocked with
+    fn check_config(&self, path: &Path, config: &EngineConfig) -> Result<(), DbError> {
+        let open = self.engine.config();
+        if config.backend != open.backend {
+            return Err(DbError::ConfigMismatch { setting: "backend".to_string() });
+        }
+        if config.archive != open.archive {
+            return Err(DbError::ConfigMismatch { setting: "archive".to_string() });
+        }
+
+        // the same key can be given in another form, only a key that doesn't unlock the database is refused
+        if config.encryption != open.encryption {
+            crypto::unlock(path, config.encryption.as_ref(), true)?;
+        }
+        Ok(())
+    }
+}
@@ -1,10 +1,12 @@
-use std::{sync::{Arc, Mutex}, time::Duration};
+use std::time::Duration;
 
 use rustyline::{error::ReadlineError, DefaultEditor};
 
-use crate::{api::Enso, error::DbError, pretty::pretty_rows, schema, sql::{ast::QueryResult, lexer::Lexer, parser::Parser}, types::{TableSchema, Value}};
+use enso::{schema, DbError, Enso, QueryResult};
 
-pub fn start_repl(db: Arc<Mutex<Enso>>) {
+use crate::pretty::pretty_rows;
+
+pub fn start_repl(mut db: Enso) {
     println!("EnsoDB v0.1");
     println!("Type '.help' for commands.\n");
 
@@ -12,12 +14,9 @@ pub fn start_repl(db: Arc<Mutex<Enso>>) {
     let mut rl = DefaultEditor::new().unwrap();
 
     loop {
-        let prompt = {
-            let db = db.lock().unwrap();
-            match db.db.as_deref() {
-                Some(name) => format!("[{}] >", name),
-                None => "[no-db] >".to_string(),
-            }
+        let prompt = match db.db.as_deref() {
+            Some(name) => format!("[{}] >", name),
+            None => "[no-db] >".to_string(),
         };
 
         match rl.readline(&prompt) {
@@ -28,26 +27,15 @@ pub fn start_repl(db: Arc<Mutex<Enso>>) {
                     continue;
                 }
 
-                rl.add_history_entry(line);
+                let _ = rl.add_history_entry(line);
 
-                let meta_cmd = {
-                    let mut db = db.lock().unwrap();
-                    handle_meta_commands(line, &mut db)
-                };
-                // if handle_meta_commands(line, &mut db) {
-                //     continue;
-                // }
-                if meta_cmd {
+                if handle_meta_commands(line, &mut db) {
                     continue;
                 }
 
-                let res = {
-                    let mut db = db.lock().unwrap();
-                    match run_query(line, &mut db) {
-                        Ok(_) => {},
-                        Err(e) => eprintln!("Error: {:?}", e),
-                    }
-                };
+                if let Err(e) = run_query(line, &mut db) {
+                    eprintln!("Error: {:?}", e);
+                }
                 // match run_query(line, &mut db) {
                 //     Ok(_) => {},
                 //     Err(e) => eprintln!("Error: {:?}", e),
@@ -70,7 +58,7 @@ pub fn start_repl(db: Arc<Mutex<Enso>>) {
             }
         }
 
-        println!("");
+        println!();
     }
 }
 
@@ -92,15 +80,18 @@ fn handle_meta_commands(line: &str, db: &mut Enso) -> bool {
             match Enso::open(name) {
                 Ok(new_db) => {
                     *db = new_db;
-                    db.create_table(
+                    let created = db.create_table(
                         "users",
                         schema! {
                             id: Int => pk,
                             name: String,
                         }
-                    ).unwrap();
+                    );
 
-                    println!("Opened database '{}'", name);
+                    match created {
+                        Ok(()) => println!("Opened database '{}'", name),
+                        Err(e) => eprintln!("Error: {:?}", e),
+                    }
                 }
                 Err(e) => eprintln!("Error: {:?}", e),
             }
@@ -115,15 +106,9 @@ fn handle_meta_commands(line: &str, db: &mut Enso) -> bool {
 }
 
 fn run_query(line: &str, db: &mut Enso) -> Result<(), DbError> {
-    let lexer = Lexer::new(line);
-    let mut parser = Parser::new(lexer)?;
-
-    let stmt = parser.parse_stmt()?;
-    let result = db.execute(stmt)?;
+    let result = db.query(line)?;
 
-    print_result(db, result);
-
-    Ok(())
+    print_result(db, result)
 }
 
 // Commands ideas:
@@ -146,13 +131,15 @@ SQL Statements:
   SELECT * FROM <table>;
   SELECT * FROM <table> WHERE <column(primary_key)> = <value>;
   DELETE FROM <table> WHERE <column(primary_key)> = <value>;
+  BEGIN; / COMMIT; / ROLLBACK;
 "#
     );
 }
 
-pub fn print_result(db: &mut Enso, result: QueryResult) {
-    let out = format_response(db, result).unwrap();
+pub fn print_result(db: &mut Enso, result: QueryResult) -> Result<(), DbError> {
+    let out = format_response(db, result)?;
     println!("{}", out);
+    Ok(())
 }
 
 // pub fn format_response(db: &Enso, res: QueryResult) -> Result<String, DbError> {
@@ -183,7 +170,7 @@ pub fn format_response(db: &mut Enso, res: QueryResult) -> Result<String, DbErro
         }
 
         QueryResult::Rows { table, rows } => {
-            if let None = rows {
+            if rows.is_none() {
                 return Ok("Empty set\n".to_string());
             }
 
@@ -193,8 +180,7 @@ pub fn format_response(db: &mut Enso, res: QueryResult) -> Result<String, DbErro
                 return Ok("Empty set\n".to_string());
             }
 
-            let current_db = db.current_db().to_string();
-            let schema = db.schema.get(&current_db, &table)?;
+            let schema = db.table_schema(&table)?;
 
             let out = pretty_rows(&schema, &rows);
 
@@ -1,23 +1,56 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::{Path, PathBuf}, sync::Arc};
 
-use crate::{error::DbError, storage::enso_data_dir, types::TableSchema};
+use crate::{crypto::{open_file, seal_file, Cipher}, error::DbError, types::TableSchema};
 
 pub struct SchemaManager {
+    // None keeps schemas in memory only
+    base: Option<PathBuf>,
     // HashMap stores db -> (table -> schema)
-    schemas: HashMap<String, HashMap<String, TableSchema>>
+    schemas: HashMap<String, HashMap<String, TableSchema>>,
+    // seals schema files of an encrypted database
+    cipher: Option<Arc<Cipher>>,
 }
 
 impl SchemaManager {
-    pub fn new() -> Self {
-        Self { schemas: HashMap::new() }
+    pub fn new(base: impl AsRef<Path>) -> Self {
+        Self { base: Some(base.as_ref().to_path_buf()), schemas: HashMap::new(), cipher: None }
     }
 
-    pub fn load_db(&mut self, db: &str) -> Result<(), DbError> {
+    pub fn in_memory() -> Self {
+        Self { base: None, schemas: HashMap::new(), cipher: None }
+    }
+
+    pub fn with_cipher(mut self, cipher: Option<Arc<Cipher>>) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    // -> Directory holding the table schemas of a database
+    pub fn db_dir(&self, db: &str) -> Option<PathBuf> {
+        self.base.as_ref().map(|base| base.join(db).join("schema"))
+    }
+
+    // -> Path to the schema file of a table
+    pub fn table_path(&self, db: &str, table: &str) -> Option<PathBuf> {
+        self.db_dir(db).map(|dir| dir.join(format!("{}.json", table)))
+    }
+
+    // -> Load the table schemas of a database, creating its schema directory if it's new
+    //    (read-only, a missing directory just means no tables)
+    pub fn load_db(&mut self, db: &str, read_only: bool) -> Result<(), DbError> {
+        let mut tables = HashMap::new();
+
         // path to table schemas
-        let base = enso_data_dir();
         // let path = format!("data/schema/{}", db);
-        let path = base.join("schema").join(db);
-        let mut tables = HashMap::new();
+        let Some(path) = self.db_dir(db) else {
+            self.schemas.insert(db.to_string(), tables);
+            return Ok(());
+        };
+        if read_only && !path.exists() {
+            self.schemas.insert(db.to_string(), tables);
+            return Ok(());
+        }
+        std::fs::create_dir_all(&path)?;
 
         // read through every table schema
         for entry in std::fs::read_dir(&path)? {
@@ -25,8 +58,8 @@ impl SchemaManager {
             let path = entry.path();
 
             if path.extension().and_then(|s| s.to_str()) == Some("json") {
-                let content = std::fs::read_to_string(&path)?;
-                let schema: TableSchema = serde_json::from_str(&content)?;
+                let content = open_file(self.cipher.as_deref(), std::fs::read(&path)?, &path)?;
+                let schema: TableSchema = serde_json::from_slice(&content)?;
 
                 tables.insert(schema.name.clone(), schema);
             }
@@ -37,25 +70,31 @@ impl SchemaManager {
     }
 
     pub fn insert(&mut self, db: &str, table: &str, schema: TableSchema) -> Result<(), DbError> {
-        if self.schemas.contains_key(&db.to_string()) {
-            // if 'db' entry exists, add table schema
-            self.schemas.get_mut(&db.to_string()).unwrap().insert(table.to_string(), schema);
-        } else {
-            // if 'db' entry doesn't exist, create a new map
-            let mut map = HashMap::new();
-            map.insert(table.to_string(), schema);
-            self.schemas.insert(db.to_string(), map);
+        // store schema in disk
+        // let path = format!("data/schema/{}/{}.json", db, table);
+        if let Some(path) = self.table_path(db, table) {
+            let json = serde_json::to_vec_pretty(&schema)?;
+            std::fs::write(&path, seal_file(self.cipher.as_deref(), json, &path))?;
         }
 
+        // create the 'db' entry if it doesn't exist, then add table schema
+        self.schemas
+            .entry(db.to_string())
+            .or_default()
+            .insert(table.to_string(), schema);
+
         Ok(())
     }
 
-    pub fn get(&mut self, db: &str, table: &str) -> Result<&TableSchema, DbError> {
-        if !self.schemas.contains_key(&db.to_string()) {
-            return Err(DbError::NoDatabaseSelected);
-        }
+    // -> Schemas of every table of a database
+    pub fn tables(&self, db: &str) -> impl Iterator<Item = &TableSchema> {
+        self.schemas.get(db).into_iter().flat_map(|tables| tables.values())
+    }
+
+    pub fn get(&self, db: &str, table: &str) -> Result<&TableSchema, DbError> {
+        let tables = self.schemas.get(db).ok_or(DbError::NoDatabaseSelected)?;
 
-        match self.schemas.get(&db.to_string()).unwrap().get(&table.to_string()) {
+        match tables.get(table) {
             Some(schema) => Ok(schema),
             None => Err(DbError::TableNotFound),
         }
@@ -0,0 +1,132 @@
+use std::{collections::{BTreeMap, HashMap}, sync::Arc};
+
+use crate::{backend::StorageBackend, error::DbError, utils::now_secs};
+
+// Snapshot reads. Every record a backend appends gets the next write sequence, and a snapshot is the
+// sequence of the last write it sees. Backends keep serving the latest state and, only while snapshots
+// are open, remember what a key held before each write that some open snapshot must not see.
+// A snapshot at seq S sees a key as it was before its first write after S, or as it is now if there was none.
+
+pub struct Snapshots<T> {
+    // sequence of every open snapshot -> number of handles on it
+    open: BTreeMap<u64, usize>,
+    // state of a key before a write made while snapshots were open, as (sequence of that write, state), oldest first
+    history: HashMap<String, Vec<(u64, Option<T>)>>,
+}
+
+impl<T> Default for Snapshots<T> {
+    fn default() -> Self {
+        Self { open: BTreeMap::new(), history: HashMap::new() }
+    }
+}
+
+impl<T: Clone> Snapshots<T> {
+    pub fn open(&mut self, seq: u64) {
+        *self.open.entry(seq).or_default() += 1;
+    }
+
+    // -> Close a snapshot, dropping the states no remaining snapshot can see
+    pub fn release(&mut self, seq: u64) {
+        if let Some(count) = self.open.get_mut(&seq) {
+            *count -= 1;
+            if *count == 0 {
+                self.open.remove(&seq);
+            }
+        }
+
+        match self.open.keys().next() {
+            None => self.history.clear(),
+            Some(&oldest) => self.history.retain(|_, versions| {
+                versions.retain(|(until, _)| *until > oldest);
+                !versions.is_empty()
+            }),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.open.is_empty()
+    }
+
+    // -> Whether a write to key has to remember the state it replaces
+    pub fn wants(&self, key: &str) -> bool {
+        match self.open.keys().next_back() {
+            None => false,
+            // once an entry is newer than every open snapshot, all of them resolve to an older one
+            Some(&newest) => self.history.get(key).and_then(|v| v.last()).is_none_or(|(until, _)| *until <= newest),
+        }
+    }
+
+    // -> Remember the state of key (None if it had no live record) before the write at seq
+    pub fn record(&mut self, key: &str, seq: u64, prev: Option<T>) {
+        self.history.entry(key.to_string()).or_default().push((seq, prev));
+    }
+
+    // -> State of key at snapshot seq, None if it's still the current one
+    pub fn resolve(&self, key: &str, seq: u64) -> Option<Option<&T>> {
+        self.history.get(key)?.iter().find(|(until, _)| *until > seq).map(|(_, state)| state.as_ref())
+    }
+
+    // -> Whether key was written after snapshot seq, which must still be open
+    pub fn written_since(&self, key: &str, seq: u64) -> bool {
+        self.resolve(key, seq).is_some()
+    }
+
+    // -> `resolve` for every key with the prefix that changed since snapshot seq
+    pub fn changed(&self, prefix: &str, seq: u64) -> Vec<(String, Option<T>)> {
+        self.history
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .filter_map(|(key, _)| self.resolve(key, seq).map(|state| (key.clone(), state.cloned())))
+            .collect()
+    }
+}
+
+// -> Put the states from `Snapshots::changed` over the current ones
+pub fn overlay<T>(states: &mut BTreeMap<String, T>, changed: Vec<(String, Option<T>)>) {
+    for (key, state) in changed {
+        match state {
+            Some(state) => states.insert(key, state),
+            None => states.remove(&key),
+        };
+    }
+}
+
+// -> Consistent point-in-time view of an engine, see `Engine::snapshot`.
+//    Writes carry on while it's open, and are released when it's dropped.
+pub struct Snapshot {
+    storage: Arc<dyn StorageBackend>,
+    seq: u64,
+}
+
+impl Snapshot {
+    pub(crate) fn new(storage: Arc<dyn StorageBackend>) -> Self {
+        let seq = storage.snapshot();
+        Self { storage, seq }
+    }
+
+    // -> Sequence of the last write the snapshot sees
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    pub fn get_raw(&self, key: &str) -> Result<Option<Vec<u8>>, DbError> {
+        let now = now_secs();
+        Ok(self.storage.get_at(key, self.seq)?.filter(|r| !r.is_expired(now)).map(|r| r.value))
+    }
+
+    pub fn scan_prefix(&self, prefix: &str) -> Result<Vec<(String, Vec<u8>)>, DbError> {
+        let now = now_secs();
+        Ok(self.storage
+            .scan_prefix_at(prefix, self.seq)?
+            .into_iter()
+            .filter(|r| !r.is_expired(now))
+            .map(|r| (r.key, r.value))
+            .collect())
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.storage.release_snapshot(self.seq);
+    }
+}
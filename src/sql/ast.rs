@@ -37,6 +37,8 @@ pub enum Stmt {
         table: String,
         columns: Vec<Column>,
         primary_key: usize,
+        // seconds, from WITH TTL
+        ttl: Option<u64>,
     },
     Insert {
         table: String,
@@ -49,14 +51,13 @@ pub enum Stmt {
     Delete {
         table: String,
         filter: Expr,
-    }
-}
-
-#[derive(Debug)]
-pub struct Rowset {
-    pub table: String,
-    pub columns: Vec<String>,
-    pub rows: Vec<Vec<Value>>,
+    },
+    Backup {
+        path: String,
+    },
+    Begin,
+    Commit,
+    Rollback,
 }
 
 #[derive(Debug)]
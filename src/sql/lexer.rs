@@ -27,7 +27,7 @@ pub enum Token {
     Star,
     Eq,
 
-    EOF,
+    Eof,
 }
 
 pub struct Lexer {
@@ -80,6 +80,9 @@ impl Lexer {
             "FROM" => Token::From,
             "WHERE" => Token::Where,
             "DELETE" => Token::Delete,
+            // words added after the first release (BACKUP, TO, WITH, TTL, BEGIN, COMMIT, ROLLBACK)
+            // stay identifiers, so tables and columns named after them keep working; the parser
+            // recognizes them where a statement expects them (see `Parser::is_word`)
             _ => Token::Ident(ident),
         }
     }
@@ -107,14 +110,15 @@ impl Lexer {
     }
 
     fn read_string(&mut self) -> Result<Token, String> {
-        // skip opening "
+        // skip opening " or '
+        let quote = self.peek();
         self.advance();
         let start = self.pos;
 
         while let Some(c) = self.peek() {
-            if c == '"' {
+            if Some(c) == quote {
                 let s: String = self.input[start..self.pos].iter().collect();
-                self.advance();  // closing "
+                self.advance();  // closing quote
                 return Ok(Token::String(s));
             }
             self.advance();
@@ -128,7 +132,7 @@ impl Lexer {
 
         let ch = match self.peek() {
             Some(c) => c,
-            None => return Ok(Token::EOF),
+            None => return Ok(Token::Eof),
         };
 
         let token = match ch {
@@ -139,7 +143,7 @@ impl Lexer {
             '*' => { self.advance(); Token::Star }
             '=' => { self.advance(); Token::Eq }
 
-            '"' => return self.read_string(),
+            '"' | '\'' => return self.read_string(),
 
             c if c.is_ascii_digit() => self.read_number(),
 
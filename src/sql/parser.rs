@@ -9,7 +9,7 @@ pub struct Parser {
 
 impl Parser {
     pub fn new(mut lexer: Lexer) -> Result<Self, DbError> {
-        let current = lexer.next_token().map_err(|e| DbError::ParseError(e))?;
+        let current = lexer.next_token().map_err(DbError::ParseError)?;
 
         Ok(Self { lexer, current })
     }
@@ -17,7 +17,7 @@ impl Parser {
     fn advance(&mut self) -> Result<(), DbError> {
         self.current = self.lexer
             .next_token()
-            .map_err(|e| DbError::ParseError(e))?;
+            .map_err(DbError::ParseError)?;
         Ok(())
     }
 
@@ -45,6 +45,22 @@ impl Parser {
         }
     }
 
+    // -> Whether the current token is the identifier word, in any case
+    fn is_word(&self, word: &str) -> bool {
+        matches!(&self.current, Token::Ident(ident) if ident.eq_ignore_ascii_case(word))
+    }
+
+    fn expect_word(&mut self, word: &str) -> Result<(), DbError> {
+        if self.is_word(word) {
+            self.advance()
+        } else {
+            Err(DbError::UnexpectedToken {
+                expected: word.into(),
+                found: format!("{:?}", self.current),
+            })
+        }
+    }
+
     // Parsing
     fn parse_create(&mut self) -> Result<Stmt, DbError> {
         self.expect(Token::Create)?;
@@ -116,10 +132,26 @@ impl Parser {
 
         let primary_key = primary_key.ok_or(DbError::PrimaryKeyMissing)?;
 
+        // WITH TTL <seconds>
+        let ttl = if self.is_word("WITH") {
+            self.advance()?;
+            self.expect_word("TTL")?;
+            match self.current {
+                Token::Int(seconds) if seconds > 0 => {
+                    self.advance()?;
+                    Some(seconds as u64)
+                }
+                _ => return Err(DbError::ParseError("TTL must be a positive number of seconds".into())),
+            }
+        } else {
+            None
+        };
+
         Ok(Stmt::CreateTable {
             table,
             columns,
             primary_key,
+            ttl,
         })
     }
 
@@ -189,6 +221,40 @@ impl Parser {
         Ok(Stmt::Delete { table, filter })
     }
 
+    fn parse_backup(&mut self) -> Result<Stmt, DbError> {
+        self.expect_word("BACKUP")?;
+        self.expect_word("TO")?;
+
+        let path = match &self.current {
+            Token::String(path) => path.clone(),
+            _ => return Err(DbError::UnexpectedToken {
+                expected: "backup directory".into(),
+                found: format!("{:?}", self.current),
+            }),
+        };
+        self.advance()?;
+
+        self.expect(Token::Semicolon)?;
+
+        Ok(Stmt::Backup { path })
+    }
+
+    // -> BEGIN, COMMIT or ROLLBACK
+    fn parse_transaction(&mut self) -> Result<Stmt, DbError> {
+        let stmt = if self.is_word("BEGIN") {
+            Stmt::Begin
+        } else if self.is_word("COMMIT") {
+            Stmt::Commit
+        } else {
+            Stmt::Rollback
+        };
+        self.advance()?;
+
+        self.expect(Token::Semicolon)?;
+
+        Ok(stmt)
+    }
+
     fn parse_where(&mut self) -> Result<Expr, DbError> {
         self.expect(Token::Where)?;
 
@@ -223,6 +289,8 @@ impl Parser {
             Token::Insert => self.parse_insert(),
             Token::Select => self.parse_select(),
             Token::Delete => self.parse_delete(),
+            _ if self.is_word("BACKUP") => self.parse_backup(),
+            _ if self.is_word("BEGIN") || self.is_word("COMMIT") || self.is_word("ROLLBACK") => self.parse_transaction(),
             _ => Err(DbError::UnsupportedStatement),
         }
     }
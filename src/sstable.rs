@@ -0,0 +1,247 @@
+use std::{fs::{rename, File}, io::Write, path::{Path, PathBuf}, sync::Arc};
+
+use crate::{bloom::BloomFilter, compression::Compression, crypto::{open_file, seal_file, Cipher}, error::DbError, handles::{read_exact_at, Handle, HandleCache}, record::{Location, Record}, storage::copy_file, utils::{decode_u32, encode_u32}};
+
+// SSTable layout (big endian):
+// [data block] ... [index] [index_offset: u64][crc32: u32]
+// Data blocks hold records (see record.rs) sorted by key, a new block is started once the
+// current one reaches BLOCK_SIZE. The sparse index has one entry per block:
+// [key_len: u32][first key of the block][block offset: u64]
+// The checksum covers the index, records carry their own.
+// In encrypted databases records are sealed one by one and the index as a whole (see crypto.rs).
+// Every table has a Bloom filter of its keys next to it (sst-000001.bloom, see bloom.rs).
+
+pub const BLOCK_SIZE: u64 = 4096;
+const FOOTER_LEN: u64 = 12;
+
+// -> Immutable sorted table, only its sparse index and Bloom filter are kept in memory
+pub struct SsTable {
+    pub name: String,
+    path: PathBuf,
+    // first key and offset of every data block
+    index: Vec<(String, u64)>,
+    // where the data blocks end and the index starts
+    data_len: u64,
+    // answers most lookups of keys the table doesn't hold without touching the disk
+    bloom: BloomFilter,
+    // whether the filter was missing or invalid and had to be rebuilt while opening
+    pub bloom_rebuilt: bool,
+    cipher: Option<Arc<Cipher>>,
+    // open handles shared by every table of the storage (see handles.rs)
+    handles: Arc<HandleCache>,
+}
+
+impl SsTable {
+    // -> Write records (sorted by key, tombstones included) as a new table. The file is
+    //    written under a temp name, synced and renamed so a table is never seen half written.
+    pub fn write<'a>(
+        path: &Path,
+        records: impl IntoIterator<Item = &'a Record>,
+        compression: Compression,
+        cipher: Option<Arc<Cipher>>,
+        handles: Arc<HandleCache>,
+    ) -> std::io::Result<Self> {
+        let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = path.with_file_name(tmp_name);
+
+        let name = Self::file_name(path);
+        let records: Vec<&Record> = records.into_iter().collect();
+        let mut bloom = BloomFilter::with_capacity(records.len());
+        let mut data = Vec::new();
+        let mut index = Vec::new();
+        let mut block_start = 0;
+        for record in records {
+            bloom.insert(&record.key);
+            let offset = data.len() as u64;
+            if index.is_empty() || offset - block_start >= BLOCK_SIZE {
+                index.push((record.key.clone(), offset));
+                block_start = offset;
+            }
+            data.extend_from_slice(&record.encode(compression, cipher.as_deref(), Location::new(&name, offset)));
+        }
+
+        let data_len = data.len() as u64;
+        let mut index_bytes = Vec::new();
+        for (key, offset) in &index {
+            index_bytes.extend_from_slice(&encode_u32(key.len() as u32));
+            index_bytes.extend_from_slice(key.as_bytes());
+            index_bytes.extend_from_slice(&offset.to_be_bytes());
+        }
+        let index_bytes = seal_file(cipher.as_deref(), index_bytes, path);
+        let crc = crc32fast::hash(&index_bytes);
+        data.extend_from_slice(&index_bytes);
+        data.extend_from_slice(&data_len.to_be_bytes());
+        data.extend_from_slice(&encode_u32(crc));
+
+        // the filter goes first so the table never shows up without it
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&data)?;
+        tmp.sync_all()?;
+        bloom.write(&Self::bloom_path(path), cipher.as_deref())?;
+        rename(&tmp_path, path)?;
+
+        Ok(Self { name, path: path.to_path_buf(), index, data_len, bloom, bloom_rebuilt: false, cipher, handles })
+    }
+
+    // -> Open a table by loading its sparse index, its file is opened through handles.
+    //    A read-only table doesn't save a filter it rebuilds.
+    pub fn open(path: &Path, cipher: Option<Arc<Cipher>>, handles: Arc<HandleCache>, read_only: bool) -> Result<Self, DbError> {
+        let name = Self::file_name(path);
+        let corruption = |offset| DbError::Corruption { segment: name.clone(), offset };
+
+        let file = handles.get(path)?;
+        let file_len = file.len();
+        if file_len < FOOTER_LEN {
+            return Err(corruption(0));
+        }
+
+        let mut footer = [0u8; FOOTER_LEN as usize];
+        read_exact_at(&file.file, &mut footer, file_len - FOOTER_LEN)?;
+        let data_len = u64::from_be_bytes(footer[0..8].try_into().unwrap());
+        let crc = decode_u32(&footer[8..12]);
+        if data_len > file_len - FOOTER_LEN {
+            return Err(corruption(file_len - FOOTER_LEN));
+        }
+
+        let mut body = vec![0u8; (file_len - FOOTER_LEN - data_len) as usize];
+        read_exact_at(&file.file, &mut body, data_len)?;
+        if crc32fast::hash(&body) != crc {
+            return Err(corruption(data_len));
+        }
+        let body = open_file(cipher.as_deref(), body, path).map_err(|_| corruption(data_len))?;
+
+        let mut index = Vec::new();
+        let mut pos = 0;
+        while pos < body.len() {
+            let entry_offset = data_len + pos as u64;
+            let len_b = body.get(pos..pos + 4).ok_or_else(|| corruption(entry_offset))?;
+            let key_len = decode_u32(len_b) as usize;
+            pos += 4;
+
+            let key_b = body.get(pos..pos + key_len).ok_or_else(|| corruption(entry_offset))?;
+            pos += key_len;
+            let off_b = body.get(pos..pos + 8).ok_or_else(|| corruption(entry_offset))?;
+            pos += 8;
+
+            let key = String::from_utf8(key_b.to_vec())?;
+            index.push((key, u64::from_be_bytes(off_b.try_into().unwrap())));
+        }
+
+        let bloom = BloomFilter::load(&Self::bloom_path(path), cipher.as_deref()).ok();
+        let bloom_rebuilt = bloom.is_none();
+        let mut table = Self { name, path: path.to_path_buf(), index, data_len, bloom: BloomFilter::with_capacity(0), bloom_rebuilt, cipher, handles };
+
+        table.bloom = match bloom {
+            Some(bloom) => bloom,
+            None => {
+                let records = table.scan_prefix(&file, "")?;
+                let mut bloom = BloomFilter::with_capacity(records.len());
+                for record in &records {
+                    bloom.insert(&record.key);
+                }
+                if !read_only {
+                    bloom.write(&Self::bloom_path(path), table.cipher.as_deref())?;
+                }
+                bloom
+            }
+        };
+
+        Ok(table)
+    }
+
+    fn file_name(path: &Path) -> String {
+        path.file_name().unwrap_or_default().to_string_lossy().into_owned()
+    }
+
+    fn bloom_path(path: &Path) -> PathBuf {
+        path.with_extension("bloom")
+    }
+
+    // -> Copy the table and its filter into dir
+    pub fn copy_to(&self, dir: &Path) -> std::io::Result<()> {
+        copy_file(&self.path, &dir.join(&self.name), None)?;
+        copy_file(&Self::bloom_path(&self.path), &Self::bloom_path(&dir.join(&self.name)), None)
+    }
+
+    // -> Delete the table and its filter, readers still holding its handle can finish
+    pub fn remove_files(&self) {
+        self.handles.evict(&self.path);
+        let _ = std::fs::remove_file(&self.path);
+        let _ = std::fs::remove_file(Self::bloom_path(&self.path));
+    }
+
+    // -> Handle to read the table through, from the cache shared with the other tables
+    pub fn file(&self) -> std::io::Result<Arc<Handle>> {
+        self.handles.get(&self.path)
+    }
+
+    // -> Whether the table may hold key, false means it certainly doesn't
+    pub fn may_contain(&self, key: &str) -> bool {
+        self.bloom.may_contain(key)
+    }
+
+    // -> Latest record of a key in this table read through file (see `file`), tombstones included
+    pub fn get(&self, file: &Handle, key: &str) -> Result<Option<Record>, DbError> {
+        if !self.bloom.may_contain(key) {
+            return Ok(None);
+        }
+
+        // last block whose first key is <= key
+        let block = self.index.partition_point(|(first, _)| first.as_str() <= key);
+        if block == 0 {
+            return Ok(None);
+        }
+
+        for record in self.read_block(file, block - 1)? {
+            if record.key == key {
+                return Ok(Some(record));
+            }
+            if record.key.as_str() > key {
+                break;
+            }
+        }
+
+        Ok(None)
+    }
+
+    // -> Records whose key starts with prefix in key order, tombstones included.
+    //    Matching keys are contiguous, so this reads blocks sequentially from the first candidate.
+    pub fn scan_prefix(&self, file: &Handle, prefix: &str) -> Result<Vec<Record>, DbError> {
+        let start = self.index.partition_point(|(first, _)| first.as_str() < prefix).saturating_sub(1);
+        let mut records = Vec::new();
+
+        for block in start..self.index.len() {
+            for record in self.read_block(file, block)? {
+                if record.key.starts_with(prefix) {
+                    records.push(record);
+                } else if record.key.as_str() > prefix {
+                    return Ok(records);
+                }
+            }
+        }
+
+        Ok(records)
+    }
+
+    fn read_block(&self, file: &Handle, block: usize) -> Result<Vec<Record>, DbError> {
+        let start = self.index[block].1;
+        let end = self.index.get(block + 1).map(|(_, offset)| *offset).unwrap_or(self.data_len);
+
+        let mut buf = vec![0u8; (end - start) as usize];
+        read_exact_at(&file.file, &mut buf, start)?;
+
+        let mut records = Vec::new();
+        let mut pos = 0;
+        while pos < buf.len() {
+            let corruption = || DbError::Corruption { segment: self.name.clone(), offset: start + pos as u64 };
+            let len = Record::record_len(&buf[pos..]).ok_or_else(corruption)?;
+            let at = Location::new(&self.name, start + pos as u64);
+            let record = buf.get(pos..pos + len).and_then(|b| Record::decode(b, self.cipher.as_deref(), at)).ok_or_else(corruption)?;
+            records.push(record);
+            pos += len;
+        }
+
+        Ok(records)
+    }
+}
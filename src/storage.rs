@@ -1,11 +1,8 @@
-use std::{collections::HashMap, fmt::format, fs::{rename, File, OpenOptions}, io::{Read, Seek, SeekFrom, Write}, num::NonZeroUsize, path::PathBuf};
+use std::{collections::{BTreeMap, HashMap, HashSet}, fs::{rename, File, OpenOptions, TryLockError}, io::{Read, Seek, SeekFrom, Write}, path::PathBuf, sync::{Arc, Mutex}, time::Instant};
 use std::path::Path;
 use chrono::Utc;
-use lru::LruCache;
-use crate::{record::Record, types::{Manifest, SegIndex}, utils::decode_u32};
-
-// const MAX_FILE_SIZE: u64 = 10 * 1000 * 1000;
-const MAX_FILE_SIZE: u64 = 111;
+use serde::de::DeserializeOwned;
+use crate::{archive::{replay_log, Archive}, compression::Compression, config::EngineConfig, crypto::{open_file, seal_file, Cipher}, durability::Durability, error::DbError, handles::{read_exact_at, Handle, HandleCache}, hint::{load_hint, write_hint}, record::{Location, Record, HEADER_LEN}, snapshot::{overlay, Snapshots}, types::{CompactionStats, CompressionStats, HintEntry, KeyDir, KeyDirEntry, Manifest, RecoveryReport, SegIndex}, utils::now_secs};
 
 pub fn enso_data_dir() -> PathBuf {
     if let Some(mut dir) = dirs::data_dir() {
@@ -21,210 +18,399 @@ pub fn enso_data_dir() -> PathBuf {
     home
 }
 
+// -> Replace a file by writing a temp file, syncing it and renaming it over the original
+pub fn write_file_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut tmp = File::create(&tmp_path)?;
+    tmp.write_all(data)?;
+    tmp.sync_all()?;
+    rename(&tmp_path, path)?;
+
+    // make the rename itself durable
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+// -> Append bytes at the end of a log file and return the offset they start at. If any part of it
+//    fails the file is cut back to its old length, so bytes that did make it to disk don't sit in
+//    front of the next append where recovery would stop reading.
+pub fn append(file: &mut File, bytes: &[u8], sync: bool) -> std::io::Result<u64> {
+    let offset = file.seek(SeekFrom::End(0))?;
+    let written = file.write_all(bytes).and_then(|_| file.flush()).and_then(|_| if sync { file.sync_data() } else { Ok(()) });
+    if let Err(e) = written {
+        file.set_len(offset)?;
+        return Err(e);
+    }
+    Ok(offset)
+}
+
+// -> Read a manifest, one that can't be decrypted or parsed is reported as corrupted
+pub fn load_manifest<T: DeserializeOwned>(path: &Path, cipher: Option<&Cipher>) -> Result<T, DbError> {
+    let corrupted = || DbError::CorruptedManifest { path: path.display().to_string() };
+    let data = open_file(cipher, std::fs::read(path)?, path).map_err(|_| corrupted())?;
+    serde_json::from_slice(&data).map_err(|_| corrupted())
+}
+
+// file in a database directory a writer holds locked while it has the database open
+pub const LOCK_FILE: &str = "LOCK";
+
+// -> Take the writer lock of the database in base, held until the returned file is closed.
+//    The OS drops it with the process, so a crash never leaves the database locked.
+pub fn lock_dir(base: &Path) -> Result<File, DbError> {
+    let file = OpenOptions::new().write(true).create(true).truncate(false).open(base.join(LOCK_FILE))?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(DbError::DatabaseLocked),
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+// -> Copy a file (only its first len bytes if given) and sync the copy
+pub fn copy_file(src: &Path, dest: &Path, len: Option<u64>) -> std::io::Result<()> {
+    let mut src = File::open(src)?;
+    let mut out = File::create(dest)?;
+    match len {
+        Some(len) => std::io::copy(&mut (&mut src).take(len), &mut out)?,
+        None => std::io::copy(&mut src, &mut out)?,
+    };
+    out.sync_all()
+}
+
+// -> Copy a directory and everything in it
+pub fn copy_dir(src: &Path, dest: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dest)?;
+    for entry in std::fs::read_dir(src)? {
+        let path = entry?.path();
+        let target = dest.join(path.file_name().unwrap_or_default());
+        if path.is_dir() {
+            copy_dir(&path, &target)?;
+        } else {
+            copy_file(&path, &target, None)?;
+        }
+    }
+    Ok(())
+}
+
+// -> Numeric id of a segment file name (enso-0001.log -> 1), None if it isn't one
+pub fn segment_id(seg: &str) -> Option<u32> {
+    seg.strip_prefix("enso-")?.strip_suffix(".log")?.parse().ok()
+}
+
+pub fn segment_name(id: u32) -> String {
+    format!("enso-{:04}.log", id)
+}
+
+fn hint_path(base: &Path, seg: &str) -> PathBuf {
+    let name = seg.trim_end_matches(".log");
+    base.join("index").join(format!("{name}.hint"))
+}
+
+// records of one log entry, a single record or a batch, with the offset each starts at
+type Entry = Vec<(u64, Record)>;
+
 pub struct Storage {
     base: PathBuf,
     file: std::fs::File,
     pub manifest: Manifest,
+    pub recovery: RecoveryReport,
+    pub config: EngineConfig,
+    // write sequence of the last appended record, also tells which writes a sync covers
+    last_seq: u64,
+    // latest location of every live key, merged across all segments
+    keydir: KeyDir,
+    // latest record of every key in the active segment, written out as its hint once sealed
+    active_index: SegIndex,
+    // id of the active segment and the highest segment id handed out so far
+    active_id: u32,
+    next_segment: u32,
+    pub compaction_stats: CompactionStats,
+    pub compression_stats: CompressionStats,
+    cipher: Option<Arc<Cipher>>,
+    // backups copying segments outside the lock and open snapshots reading them,
+    // segments compaction replaces meanwhile are kept until both are done
+    backups_running: usize,
+    deferred_removals: Vec<PathBuf>,
+    // locations open snapshots still see in place of newer writes (see snapshot.rs)
+    snapshots: Snapshots<KeyDirEntry>,
+    // sealed segments kept for point-in-time recovery, when `EngineConfig::archive` is on
+    archive: Option<Archive>,
+    // read handles of segments, shared with readers outside the lock
+    handles: Arc<HandleCache>,
 }
 
 impl Storage {
-    pub fn new() -> Self {
-        let base = enso_data_dir();
+    // -> Open storage rooted at the given database directory, whose writer lock the caller holds
+    // (cipher set when the database is encrypted, see crypto.rs)
+    pub fn open(base: impl AsRef<Path>, config: EngineConfig, cipher: Option<Arc<Cipher>>) -> Result<Self, DbError> {
+        let base = base.as_ref().to_path_buf();
+        let manifest_path = base.join("manifest.json");
 
-        std::fs::create_dir_all(base.join("segments")).unwrap();
-        std::fs::create_dir_all(base.join("index")).unwrap();
-        std::fs::create_dir_all(base.join("schema")).unwrap();
+        // a read-only open never creates or repairs anything
+        if config.read_only {
+            if !manifest_path.exists() {
+                return Err(DbError::DatabaseNotFound);
+            }
+        } else {
+            std::fs::create_dir_all(base.join("segments"))?;
+            std::fs::create_dir_all(base.join("index"))?;
+            std::fs::create_dir_all(base.join("schema"))?;
+        }
 
-        let manifest_path = base.join("manifest.json");
         // let manifest_path = "data/manifest.json";
         // std::fs::create_dir_all("data/index").unwrap();
 
         let manifest = if manifest_path.exists() {
-            let data = std::fs::read_to_string(manifest_path).unwrap();
-            serde_json::from_str::<Manifest>(&data).unwrap()
+            load_manifest::<Manifest>(&manifest_path, cipher.as_deref())?
         } else {
             let manifest = Manifest {
                 active_segment: "enso-0001.log".to_string(),
                 segments: vec!["enso-0001.log".to_string()],
                 last_compaction: None,
+                last_seq: 0,
             };
 
             // std::fs::create_dir_all("data/segments").unwrap();
             // std::fs::create_dir_all("data/index").unwrap();
-            std::fs::write(&manifest_path, serde_json::to_string_pretty(&manifest).unwrap()).unwrap();
+            let data = serde_json::to_vec_pretty(&manifest)?;
+            write_file_atomic(&manifest_path, &seal_file(cipher.as_deref(), data, &manifest_path))?;
 
             manifest
         };
 
-        // Create idx file(s)
-        // std::fs::create_dir_all("data/index").unwrap();
-        // let idx_path = format!("data/index/{}.idx", manifest.active_segment.trim_end_matches(".log"));
-        let idx_path = base.join("index").join(manifest.active_segment.trim_end_matches(".log")).with_extension("idx");
-        if !Path::new(&idx_path).exists() {
-            File::create(idx_path).unwrap();
-        }
+        // the keydir refers to segments by the ids in their names
+        let corrupted = || DbError::CorruptedManifest { path: manifest_path.display().to_string() };
+        let ids = manifest.segments.iter().map(|s| segment_id(s).ok_or_else(corrupted)).collect::<Result<Vec<_>, _>>()?;
+        let active_id = segment_id(&manifest.active_segment).ok_or_else(corrupted)?;
+        let next_segment = ids.into_iter().max().unwrap_or(0);
 
         // Create segment file
         // let active_path = format!("data/segments/{}", manifest.active_segment);
         let active_path = base.join("segments").join(&manifest.active_segment);
         let file = OpenOptions::new()
             .read(true)
-            .append(true)
-            .create(true)
+            .append(!config.read_only)
+            .create(!config.read_only)
             .open(active_path)
-            .unwrap();
+            .map_err(|e| DbError::from(e).for_segment(&manifest.active_segment))?;
+
+        // a new archive starts from the sealed segments there are, history compacted away before is lost
+        let archive = if config.archive && !config.read_only {
+            let mut archive = Archive::open(&base, cipher.clone())?;
+            if archive.is_empty() {
+                for seg in manifest.segments.iter().filter(|&s| *s != manifest.active_segment) {
+                    archive.add_sealed(&base.join("segments").join(seg), seg).map_err(|e| DbError::from(e).for_segment(seg))?;
+                }
+            }
+            Some(archive)
+        } else {
+            None
+        };
 
-        Self { base, file, manifest }
+        // read-only, every segment stays open (see recover)
+        let capacity = if config.read_only { usize::MAX } else { config.max_open_files };
+        let handles = Arc::new(HandleCache::new(capacity));
+        let mut storage = Self {
+            base,
+            file,
+            manifest,
+            recovery: RecoveryReport::default(),
+            config,
+            last_seq: 0,
+            keydir: KeyDir::new(),
+            active_index: SegIndex::new(),
+            active_id,
+            next_segment,
+            compaction_stats: CompactionStats::default(),
+            compression_stats: CompressionStats::default(),
+            cipher,
+            backups_running: 0,
+            deferred_removals: Vec::new(),
+            snapshots: Snapshots::default(),
+            archive,
+            handles,
+        };
+        storage.recovery = storage.recover()?;
+        Ok(storage)
     }
 
-    pub fn get_base(&self) -> &Path {
-        &self.base
-    }
+    // -> Build the keydir from the hint files of sealed segments and a scan of the active one,
+    //    repairing what a crash left behind on the way: a torn record at the tail of the
+    //    active segment is truncated, missing or invalid hints are regenerated from their log
+    fn recover(&mut self) -> Result<RecoveryReport, DbError> {
+        let mut report = RecoveryReport::default();
+        self.last_seq = self.manifest.last_seq;
+
+        // oldest to newest, so newer entries override older ones
+        for seg in self.manifest.segments.clone() {
+            let is_active = seg == self.manifest.active_segment;
+            let seg_path = self.base.join("segments").join(&seg);
+            if !is_active && !seg_path.exists() {
+                return Err(DbError::MissingSegment { segment: seg });
+            }
+            // a read-only open holds on to every segment it indexes, the writer can compact them away under it
+            if self.config.read_only {
+                self.handles.get(&seg_path).map_err(|e| DbError::from(e).for_segment(&seg))?;
+            }
 
-    pub fn save_manifest(&self) {
-        let path = self.base.join("manifest.json");
-        let data = serde_json::to_string_pretty(&self.manifest).unwrap();
-        // std::fs::write("data/manifest.json", data).unwrap();
-        std::fs::write(path, data).unwrap();
+            let index = if is_active {
+                let (index, valid_len, last_seq) = Self::scan_segment(&self.file, &seg, self.cipher.as_deref())?;
+                self.last_seq = self.last_seq.max(last_seq);
+                let file_len = self.file.metadata()?.len();
+
+                // only the active segment can end in a half-written record,
+                // read-only it may be one the writer is still appending
+                if valid_len < file_len && !self.config.read_only {
+                    self.file.set_len(valid_len)?;
+                    self.file.sync_all()?;
+                    report.truncated_bytes += file_len - valid_len;
+                }
+
+                self.active_index = index.clone();
+                index
+            } else {
+                match load_hint(&self.hint_path(&seg), self.cipher.as_deref()) {
+                    Ok(index) => index,
+                    Err(_) => {
+                        let file = OpenOptions::new().read(true).open(&seg_path)?;
+                        let (index, valid_len, _) = Self::scan_segment(&file, &seg, self.cipher.as_deref())?;
+                        if valid_len < file.metadata()?.len() {
+                            return Err(DbError::Corruption { segment: seg, offset: valid_len });
+                        }
+
+                        if !self.config.read_only {
+                            write_hint(&self.hint_path(&seg), &index, self.cipher.as_deref())?;
+                        }
+                        report.restored_entries += index.len();
+                        report.rebuilt_indexes.push(seg.clone());
+                        index
+                    }
+                }
+            };
+
+            let id = self.id_of(&seg)?;
+            Self::merge_into_keydir(&mut self.keydir, id, &index);
+        }
+
+        Ok(report)
     }
 
-    pub fn append_idx_entry(idx_path: &Path, key: &str, offset: u64) -> std::io::Result<()> {
-        let mut f = OpenOptions::new().create(true).append(true).open(idx_path)?;
-        f.write_all(&(key.len() as u32).to_be_bytes())?;
-        f.write_all(key.as_bytes())?;
-        f.write_all(&offset.to_be_bytes())?;
-        f.flush()?;
-        Ok(())
+    fn merge_into_keydir(keydir: &mut KeyDir, segment: u32, index: &SegIndex) {
+        for (key, entry) in index {
+            Self::apply_to_keydir(keydir, segment, key, entry);
+        }
     }
 
-    pub fn load_idx(&self, idx_path: &Path) -> std::io::Result<SegIndex> {
-        let mut map = HashMap::new();
-        let mut f = File::open(idx_path)?;
+    fn apply_to_keydir(keydir: &mut KeyDir, segment: u32, key: &str, entry: &HintEntry) {
+        if entry.deleted {
+            keydir.remove(key);
+        } else {
+            keydir.insert(key.to_string(), KeyDirEntry { segment, offset: entry.offset, timestamp: entry.timestamp });
+        }
+    }
 
-        loop {
-            let mut len_b = [0u8; 4];
-            if let Err(e) = f.read_exact(&mut len_b) {
-                if e.kind() == std::io::ErrorKind::UnexpectedEof { break; }
-                else { return Err(e); }
+    // -> Walk a segment log and build its index, also returns the length of the valid prefix
+    //    and the highest write sequence in it.
+    //    A record (or batch) cut short by the end of the file is treated as a torn write (see
+    //    `Record::is_torn_tail`), anything else invalid is corruption.
+    fn scan_segment(file: &File, seg: &str, cipher: Option<&Cipher>) -> Result<(SegIndex, u64, u64), DbError> {
+        let mut index = HashMap::new();
+        let mut last_seq = 0;
+        let file_len = file.metadata()?.len();
+        let mut offset = 0;
+
+        while offset < file_len {
+            match Self::read_entry(file, file_len, seg, offset, cipher) {
+                Ok(Some((records, len))) => {
+                    for (at, record) in records {
+                        last_seq = last_seq.max(record.seq);
+                        index.insert(record.key, HintEntry { offset: at, timestamp: record.timestamp, deleted: record.deleted });
+                    }
+                    offset += len;
+                }
+                Ok(None) => break,
+                Err(DbError::Corruption { .. }) if Self::is_torn_tail(file, offset, file_len)? => break,
+                Err(e) => return Err(e),
             }
-            let key_len = u32::from_be_bytes(len_b) as usize;
+        }
 
-            let mut key_b = vec![0u8; key_len];
-            f.read_exact(&mut key_b)?;
-            let mut off_b = [0u8; 8];
-            f.read_exact(&mut off_b)?;
+        Ok((index, offset, last_seq))
+    }
 
-            let key = String::from_utf8(key_b).expect("index key not utf8");
-            let off = u64::from_be_bytes(off_b);
+    fn is_torn_tail(file: &File, offset: u64, file_len: u64) -> Result<bool, DbError> {
+        let mut tail = vec![0u8; (file_len - offset) as usize];
+        read_exact_at(file, &mut tail, offset)?;
+        Ok(Record::is_torn_tail(&tail))
+    }
 
-            map.insert(key, off);
-        }
+    pub fn save_manifest(&self) -> std::io::Result<()> {
+        let path = self.base.join("manifest.json");
+        let data = serde_json::to_vec_pretty(&self.manifest)?;
+        // std::fs::write("data/manifest.json", data).unwrap();
+        write_file_atomic(&path, &seal_file(self.cipher.as_deref(), data, &path))
+    }
 
-        Ok(map)
+    // -> Handle to the active segment and the last write sequence it holds, for syncing outside the lock
+    pub fn sync_handle(&self) -> std::io::Result<(File, u64)> {
+        Ok((self.file.try_clone()?, self.last_seq))
     }
 
-    pub fn rebuild_index(&mut self) -> std::io::Result<LruCache<String, SegIndex>> {
-        let mut cache: LruCache<String, SegIndex> = LruCache::new(NonZeroUsize::new(4).unwrap());
-        let segments = &self.manifest.segments;
-        let segments: Vec<String> = segments.iter().take(4).cloned().collect();
+    // -> Id of a segment the manifest lists, see `segment_id`
+    fn id_of(&self, seg: &str) -> Result<u32, DbError> {
+        segment_id(seg).ok_or_else(|| DbError::CorruptedManifest { path: self.base.join("manifest.json").display().to_string() })
+    }
 
-        for s in segments {
-            let seg_name = &s[..s.rfind('.').unwrap()];
-            // let idx_path = format!("data/index/{}.idx", seg_name);
-            let idx_path = self.base.join("index").join(format!("{}.idx", seg_name));
-            // let idx_path: String = idx_path.to_string_lossy().to_owned();
-            let seg_index = match self.load_idx(&idx_path) {
-                Ok(map) => map,
-                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                    // First run: no index files created
-                    HashMap::new()
-                }
-                Err(e) => return Err(e),
-            };
-            cache.put(s.to_string(), seg_index);
-        }
+    fn hint_path(&self, seg: &str) -> PathBuf {
+        hint_path(&self.base, seg)
+    }
 
-        Ok(cache)
-    }
-
-    fn active_idx_path(&self) -> PathBuf {
-        // let seg = &self.manifest.active_segment;
-        // let name = &seg[..seg.rfind('.').unwrap()];
-        // format!("data/index/{}.idx", name)
-
-        let name = self.manifest.active_segment.trim_end_matches(".log");
-        self.base.join("index").join(format!("{name}.idx"))
-    }
-
-    fn next_segment_name(&self) -> String {
-        let last = self.manifest.segments.last().unwrap();
-        let num: u32 = last
-            .trim_start_matches("enso-")
-            .trim_end_matches(".log")
-            .parse()
-            .unwrap();
-
-        format!("enso-{:04}.log", num+1)
-    }
-
-    // pub fn rebuild_index(&mut self) -> std::io::Result<HashMap<String, u64>> {
-    //     let mut index = HashMap::new();
-    //     self.file.seek(SeekFrom::Start(0))?;
-    //
-    //     loop {
-    //         // Read header
-    //         let mut header = [0u8; 17];
-    //         match self.file.read_exact(&mut header) {
-    //             Ok(_) => {},
-    //             Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-    //             Err(e) => return Err(e),
-    //         }
-    //
-    //         let key_len = decode_u32(&header[0..4]) as usize;
-    //         let val_len = decode_u32(&header[4..8]) as usize;
-    //         let record_len = 17 + key_len + val_len;
-    //
-    //         // Read the rest of the record
-    //         let mut buf = vec![0u8; record_len-17];
-    //         self.file.read_exact(&mut buf)?;
-    //
-    //         // Reconstruct the record bytes
-    //         let mut full = Vec::new();
-    //         full.extend_from_slice(&header);
-    //         full.extend_from_slice(&buf);
-    //         let record = Record::deserialize(&full);
-    //
-    //         // Read offset (current positon - length of record)
-    //         let offset = self.file.stream_position()? - (record_len as u64);
-    //
-    //         // Insert in index
-    //         index.insert(record.key, offset);
-    //     }
-    //
-    //     Ok(index)
-    // }
+    // -> Reserve the next segment id, ids are never handed out twice so a compaction
+    //    running outside the lock and a rotation can't pick the same name
+    fn next_segment_name(&mut self) -> String {
+        self.next_segment += 1;
+        segment_name(self.next_segment)
+    }
 
     fn rotate_segment(&mut self) -> std::io::Result<()> {
+        // writes to the old segment must be durable before we move on from it
+        if self.config.durability != Durability::None {
+            self.file.sync_data()?;
+        }
+
+        // seal the old segment with its hint file
+        let old_seg = self.manifest.active_segment.clone();
+        write_hint(&self.hint_path(&old_seg), &self.active_index, self.cipher.as_deref())?;
+        self.active_index.clear();
+        if let Some(archive) = &mut self.archive {
+            archive.add_sealed(&self.base.join("segments").join(&old_seg), &old_seg)?;
+        }
+
         let new_seg = self.next_segment_name();
 
         // create new segment file
         // let seg_path = format!("data/segments/{}", new_seg);
         let seg_path = self.base.join("segments").join(&new_seg);
+        // a file by this name can only be left over from a compaction that crashed before its manifest swap
+        let _ = std::fs::remove_file(&seg_path);
+        let _ = std::fs::remove_file(self.hint_path(&new_seg));
         let file = OpenOptions::new()
             .read(true)
             .append(true)
             .create(true)
             .open(&seg_path)?;
 
-        // create index file
-        // let idx_path = format!("data/index/{}.idx", new_seg.trim_end_matches(".log"));
-        let idx_path = self.base.join("index").join(format!("{}.idx", new_seg.trim_end_matches(".log")));
-        File::create(idx_path)?;
-
         // update manifest
         self.manifest.segments.push(new_seg.clone());
+        self.manifest.last_seq = self.last_seq;
         self.manifest.active_segment = new_seg;
-        self.save_manifest();
+        self.active_id = self.next_segment;
+        self.save_manifest()?;
 
         // switch active file
         self.file = file;
@@ -232,190 +418,488 @@ impl Storage {
         Ok(())
     }
 
-    // -> File compaction if size exceeds threshold
-    fn read_seg_into_map(&self, seg: &str, mut records: HashMap<String, Record>) -> std::io::Result<HashMap<String, Record>> {
-        // let base = enso_data_dir();
-        let mut file = OpenOptions::new()
-            .read(true)
-            // .open(format!("data/segments/{}", seg))?;
-            .open(self.base.join("segments").join(seg))?;
-        // let mut records = HashMap::new();
-
-        file.seek(SeekFrom::Start(0))?;
-        loop {
-            // first read header (16 bytes)
-            let mut header = [0u8; 17];
-            if file.read_exact(&mut header).is_err() { break; }
-            
-            let key_len = decode_u32(&header[0..4]) as usize;
-            let val_len = decode_u32(&header[4..8]) as usize;
-            let record_len = 17 + key_len + val_len;
-            
-            // now read the rest
-            let mut buf = vec![0u8; record_len-17];
-            file.read_exact(&mut buf)?;
-
-            // reconstruct full buffer
-            let mut full = Vec::new();
-            full.extend_from_slice(&header);
-            full.extend_from_slice(&buf);
-
-            // deserialize and insert into records
-            let record = Record::deserialize(&full);
-            if record.deleted == false {
-                records.insert(record.key.clone(), record);
+    // -> Merge sealed segments while only holding the storage lock to pick them and to swap the result in,
+    //    reads and writes carry on against the keydir and active segment in between
+    pub fn compact(storage: &Mutex<Self>) -> Result<(), DbError> {
+        let started = Instant::now();
+
+        let (job, mut blocked) = {
+            let mut storage = storage.lock().unwrap();
+            let locked = Instant::now();
+            (storage.plan_compaction(), locked.elapsed())
+        };
+        let job = match job {
+            Some(job) => job,
+            None => return Ok(()),
+        };
+
+        let index = job.run()?;
+
+        let mut storage = storage.lock().unwrap();
+        let locked = Instant::now();
+        storage.finish_compaction(&job, index)?;
+        blocked += locked.elapsed();
+
+        storage.compaction_stats.record(job.segments.len(), started.elapsed(), blocked);
+        Ok(())
+    }
+
+    // -> Pick the sealed segments to compact and reserve a name for the merged one.
+    //    Sealed segments never change, so the returned job can run without holding the storage lock.
+    pub fn plan_compaction(&mut self) -> Option<CompactionJob> {
+        // every sealed segment, oldest first
+        let segments: Vec<String> = self.manifest.segments.iter().filter(|&s| *s != self.manifest.active_segment).cloned().collect();
+        if segments.len() < 2 { return None; }
+
+        Some(CompactionJob {
+            base: self.base.clone(),
+            name: self.next_segment_name(),
+            segments,
+            compression: self.config.compression,
+            cipher: self.cipher.clone(),
+        })
+    }
+
+    // -> Swap a finished compaction in: repoint the keydir, replace the merged segments in the manifest
+    //    and delete them. Writes made while the job ran live in newer segments and are left alone.
+    pub fn finish_compaction(&mut self, job: &CompactionJob, index: SegIndex) -> Result<(), DbError> {
+        let segments = &job.segments;
+
+        // point keys still living in the old segments at the new one
+        let removed = segments.iter().map(|s| self.id_of(s)).collect::<Result<HashSet<u32>, _>>()?;
+        let new_id = self.id_of(&job.name)?;
+        self.keydir.retain(|key, entry| {
+            if !removed.contains(&entry.segment) {
+                return true;
             }
+
+            match index.get(key) {
+                Some(hint) if !hint.deleted => {
+                    *entry = KeyDirEntry { segment: new_id, offset: hint.offset, timestamp: hint.timestamp };
+                    true
+                }
+                _ => false,
+            }
+        });
+
+        // the merged segment takes the place of the oldest one it replaces, keeping the manifest ordered by age
+        let timestamp = Utc::now();
+        let pos = self.manifest.segments.iter().position(|s| *s == segments[0]).unwrap();
+        self.manifest.segments.insert(pos, job.name.clone());
+        self.manifest.segments.retain(|s| !segments.contains(s));
+        self.manifest.last_compaction = Some(timestamp.to_string());
+        self.save_manifest()?;
+
+        // delete old segments
+        for seg in segments.iter() {
+            self.deferred_removals.extend([self.base.join("segments").join(seg), self.hint_path(seg)]);
         }
-        
-        Ok(records)
+        self.remove_deferred();
+
+        Ok(())
     }
 
-    fn write_compacted_records(&self, name: &str, records: HashMap<String, Record>) -> std::io::Result<()> {
-        // base file directory
-        // let base = enso_data_dir();
+    // -> Copy a consistent snapshot of the database into dest while reads and writes carry on.
+    //    The lock is only held to pin the current segments: sealed ones never change and the active
+    //    one is only appended to, so copying it up to its current length captures every write before the backup.
+    pub fn backup(storage: &Mutex<Self>, dest: &Path) -> Result<(), DbError> {
+        let job = storage.lock().unwrap().plan_backup()?;
+        let result = job.run(dest);
+        storage.lock().unwrap().finish_backup();
+        result
+    }
 
-        // create temp files
-        // let mut tmp_log_file = File::create(format!("data/segments/{}.log.tmp", name))?;
-        // let mut tmp_idx_file = File::create(format!("data/index/{}.idx.tmp", name))?;
-        let mut tmp_log_file = File::create(self.base.join("segments").join(format!("{}.log.tmp", name)))?;
-        let mut tmp_idx_file = File::create(self.base.join("index").join(format!("{}.idx.tmp", name)))?;
+    fn plan_backup(&mut self) -> std::io::Result<BackupJob> {
+        self.backups_running += 1;
+        Ok(BackupJob {
+            base: self.base.clone(),
+            manifest: self.manifest.clone(),
+            active_len: self.file.metadata()?.len(),
+            cipher: self.cipher.clone(),
+        })
+    }
 
-        let mut records: Vec<_> = records.into_iter().collect();
-        records.sort_by(|a, b| a.0.cmp(&b.0));
-        for (key, record) in records.iter() {
-            // write record to tmp log
-            let record_bytes = &record.serialize();
-            let offset = tmp_log_file.seek(SeekFrom::End(0))?;
-            tmp_log_file.write_all(&record_bytes)?;
-            tmp_log_file.flush()?;
+    fn finish_backup(&mut self) {
+        self.backups_running -= 1;
+        self.remove_deferred();
+    }
 
-            // write offset to tmp idx
-            tmp_idx_file.write_all(&(key.len() as u32).to_be_bytes())?;
-            tmp_idx_file.write_all(key.as_bytes())?;
-            tmp_idx_file.write_all(&offset.to_be_bytes())?;
-            tmp_idx_file.flush()?;
+    // -> Delete the segments compaction replaced once no backup or snapshot can still read them
+    fn remove_deferred(&mut self) {
+        if self.backups_running == 0 && self.snapshots.is_empty() {
+            for file in self.deferred_removals.drain(..) {
+                self.handles.evict(&file);
+                let _ = std::fs::remove_file(file);
+            }
         }
+    }
 
-        // atomic swap
-        tmp_log_file.sync_all()?;
-        tmp_idx_file.sync_all()?;
+    // -> Pin the current state for snapshot reads, returns the write sequence it's at
+    pub fn snapshot(&mut self) -> u64 {
+        self.snapshots.open(self.last_seq);
+        self.last_seq
+    }
 
-        // rename(format!("data/segments/{}.log.tmp", name), format!("data/segments/{}.log", name))?;
-        // rename(format!("data/index/{}.idx.tmp", name), format!("data/index/{}.idx", name))?;
-        rename(self.base.join("segments").join(format!("{}.log.tmp", name)), self.base.join("segments").join(format!("{}.log", name)))?;
-        rename(self.base.join("index").join(format!("{}.idx.tmp", name)), self.base.join("index").join(format!("{}.idx", name)))?;
+    // -> Whether key was written after the open snapshot at seq
+    pub fn written_since(&self, key: &str, seq: u64) -> bool {
+        self.snapshots.written_since(key, seq)
+    }
 
-        // Ok(format!("data/segments/{}.log", name))
-        Ok(())
+    pub fn release_snapshot(&mut self, seq: u64) {
+        self.snapshots.release(seq);
+        self.remove_deferred();
     }
 
-    pub fn compact_segments(&mut self) -> std::io::Result<(Vec<String>, String)> {
-        // println!("Compacting start...");
-        let segments = &self.manifest.segments;
-        let segments: Vec<String> = segments.iter().filter(|&s| s.to_string() != self.manifest.active_segment).cloned().collect();
-        if segments.len() < 2 { return Ok((vec![], String::new())); }
+    // -> Record of a key as snapshot seq sees it. The lock is only held to find where it lives:
+    //    records are never rewritten in place and compaction keeps the segments it replaces while snapshots are open.
+    pub fn get_at(storage: &Mutex<Self>, key: &str, seq: u64) -> Result<Option<Record>, DbError> {
+        let (entry, reader) = {
+            let storage = storage.lock().unwrap();
+            let entry = match storage.snapshots.resolve(key, seq) {
+                Some(state) => state.copied(),
+                None => storage.keydir.get(key).copied(),
+            };
+            (entry, storage.reader())
+        };
 
-        // compress all segments into one map
-        let mut records: HashMap<String, Record> = HashMap::new();
-        for seg in segments.iter().rev().clone() {
-            records = self.read_seg_into_map(&seg, records)?;
+        entry.map(|entry| reader.read(&entry)).transpose()
+    }
+
+    // -> Records whose key starts with prefix as snapshot seq sees them, ordered by key
+    pub fn scan_prefix_at(storage: &Mutex<Self>, prefix: &str, seq: u64) -> Result<Vec<Record>, DbError> {
+        let (entries, reader) = {
+            let storage = storage.lock().unwrap();
+            let mut entries: BTreeMap<String, KeyDirEntry> = storage.keydir
+                .iter()
+                .filter(|(key, _)| key.starts_with(prefix))
+                .map(|(key, entry)| (key.clone(), *entry))
+                .collect();
+            overlay(&mut entries, storage.snapshots.changed(prefix, seq));
+            (entries, storage.reader())
+        };
+
+        entries.into_values().map(|entry| reader.read(&entry)).collect()
+    }
+
+    // -> Live records as they were at `until` (unix seconds), replayed from the archive and the active segment.
+    //    Archived segments never change and the active one is only appended to, so the lock is only
+    //    held to see how far to read.
+    pub fn records_at(storage: &Mutex<Self>, until: u64) -> Result<Vec<Record>, DbError> {
+        let (archive, active, active_len) = {
+            let storage = storage.lock().unwrap();
+            let archive = storage.archive.clone().ok_or(DbError::ArchiveDisabled)?;
+            let active = storage.base.join("segments").join(&storage.manifest.active_segment);
+            (archive, active, storage.file.metadata()?.len())
+        };
+
+        let mut state = BTreeMap::new();
+        archive.replay(until, &mut state)?;
+        replay_log(&active, Some(active_len), archive.cipher(), until, &mut state)?;
+        Ok(state.into_values().filter(|r| !r.deleted).collect())
+    }
+
+    // -> Check every record of the database under base against its checksum, a torn tail included.
+    //    Used to validate a backup before it's restored.
+    pub fn verify(base: &Path, cipher: Option<Arc<Cipher>>) -> Result<(), DbError> {
+        let cipher = cipher.as_deref();
+        let manifest: Manifest = load_manifest(&base.join("manifest.json"), cipher)?;
+
+        for seg in &manifest.segments {
+            let file = File::open(base.join("segments").join(seg)).map_err(|e| DbError::from(e).for_segment(seg))?;
+            let (_, valid_len, _) = Self::scan_segment(&file, seg, cipher)?;
+            if valid_len < file.metadata()?.len() {
+                return Err(DbError::Corruption { segment: seg.clone(), offset: valid_len });
+            }
         }
 
-        // write to new segment
-        // let name = &segments[0][..segments[0].rfind('.').unwrap()];
-        // let name = format!("{}-compacted", name);
-        let name = self.next_segment_name();
-        self.write_compacted_records(&name[..name.rfind('.').unwrap()], records)?;
-        // println!("New segment created");
+        Ok(())
+    }
 
-        let timestamp = Utc::now();
-        self.manifest.segments.retain(|s| !segments.contains(s));
-        self.manifest.segments.push(name.clone());
-        self.manifest.last_compaction = Some(timestamp.to_string());
-        self.save_manifest();
-        // println!("Manifest saved");
+    // -> Append data (record) to end of log file, returns offset and write sequence
+    pub fn append(&mut self, record: &Record) -> std::io::Result<(u64, u64)> {
+        let record = Record { seq: self.last_seq + 1, ..record.clone() };
+        let (compression, cipher) = (self.config.compression, self.cipher.clone());
+        let (offset, len) = self.write_entry(|at| record.encode(compression, cipher.as_deref(), at))?;
+        self.compression_stats.record(record.encoded_len(), len);
+        self.index_record(&record, offset);
 
-        // delete old segments
-        for seg in segments.clone() {
-            let seg_name = &seg[..seg.rfind('.').unwrap()];
-            // let _ = std::fs::remove_file(format!("data/segments/{}.log", seg_name));
-            // let _ = std::fs::remove_file(format!("data/index/{}.idx", seg_name));
-            let _ = std::fs::remove_file(self.base.join("segments").join(format!("{}.log", seg_name)));
-            let _ = std::fs::remove_file(self.base.join("index").join(format!("{}.idx", seg_name)));
-
-            // cleanup LRU cache/index
-            // self.index.pop(&seg_name);
+        Ok((offset, self.last_seq))
+    }
+
+    // -> Append records as one batch entry, recovery sees either all of them or none.
+    //    Returns the write sequence of the last one.
+    pub fn append_batch(&mut self, records: &[Record]) -> std::io::Result<u64> {
+        if records.is_empty() {
+            return Ok(self.last_seq);
         }
 
-        // println!("Compacting end...");
+        let records: Vec<Record> = records
+            .iter()
+            .zip(self.last_seq + 1..)
+            .map(|(record, seq)| Record { seq, ..record.clone() })
+            .collect();
+        let (compression, cipher) = (self.config.compression, self.cipher.clone());
+        let mut offsets = Vec::new();
+        let (offset, len) = self.write_entry(|at| {
+            let (bytes, at_offsets) = Record::encode_batch(&records, compression, cipher.as_deref(), at);
+            offsets = at_offsets;
+            bytes
+        })?;
+        self.compression_stats.record(records.iter().map(|r| r.encoded_len()).sum(), len);
+
+        for (record, at) in records.iter().zip(offsets) {
+            self.index_record(record, offset + at as u64);
+        }
 
-        Ok((segments, name))
+        Ok(self.last_seq)
     }
 
-    // -> Append data (record) to end of log file, returns offset
-    pub fn append(&mut self, record: &Record) -> std::io::Result<u64> {
-        let record_bytes = record.serialize();
+    // -> Write an entry at the end of the active segment, moving on to a new segment first if it would
+    //    overflow. encode gets where the entry goes, encrypted entries are sealed to it and encoded again
+    //    after a move. Returns the offset it was written at and its length.
+    fn write_entry(&mut self, mut encode: impl FnMut(Location) -> Vec<u8>) -> std::io::Result<(u64, usize)> {
         let cur_size = self.file.metadata()?.len();
+        let mut bytes = encode(Location::new(&self.manifest.active_segment, cur_size));
 
         // Check if appending would overflow file size threshold
-        if cur_size + record_bytes.len() as u64 > MAX_FILE_SIZE {
+        if cur_size + bytes.len() as u64 > self.config.segment_size {
             self.rotate_segment()?;
+            bytes = encode(Location::new(&self.manifest.active_segment, self.file.metadata()?.len()));
         }
 
         // Append to log
-        let offset = self.file.seek(SeekFrom::End(0))?;
-        self.file.write_all(&record_bytes)?;
-        self.file.flush()?;
-
-        // Write index entry
-        let idx_path = self.active_idx_path();
-        Self::append_idx_entry(&idx_path, &record.key, offset)?;
-
-        Ok(offset as u64)
-    }
-
-    // -> Read data (key-value pair) at given offset
-    pub fn read_at(&mut self, offset: u64) -> std::io::Result<Record> {
-        self.file.seek(SeekFrom::Start(offset))?;
-        
-        // first read header (16 bytes)
-        let mut header = [0u8; 17];
-        self.file.read_exact(&mut header)?;
-        
-        let key_len = decode_u32(&header[0..4]) as usize;
-        let val_len = decode_u32(&header[4..8]) as usize;
-        let record_len = 17 + key_len + val_len;
-        
-        // now read the rest
-        let mut buf = vec![0u8; record_len];
-        self.file.seek(SeekFrom::Start(offset))?;
-        self.file.read_exact(&mut buf)?;
-        
-        Ok(Record::deserialize(&buf))
+        let offset = append(&mut self.file, &bytes, self.config.durability == Durability::FlushEveryWrite)?;
+        let path = self.base.join("segments").join(&self.manifest.active_segment);
+        self.handles.set_len(&path, offset + bytes.len() as u64);
+        Ok((offset, bytes.len()))
     }
 
-    pub fn read_from_segment(&mut self, seg: &str, offset: u64) -> std::io::Result<Record> {
-        // let seg_path = format!("data/segments/{}", seg);
-        let seg_path = self.base.join("segments").join(seg);
-        let mut seg_file = OpenOptions::new()
-            .read(true)
-            .open(seg_path)?;
-
-        seg_file.seek(SeekFrom::Start(offset))?;
-        
-        // first read header (16 bytes)
-        let mut header = [0u8; 17];
-        seg_file.read_exact(&mut header)?;
-        
-        let key_len = decode_u32(&header[0..4]) as usize;
-        let val_len = decode_u32(&header[4..8]) as usize;
-        let record_len = 17 + key_len + val_len;
-        
+    // -> Point the indexes at a record just written to the active segment
+    fn index_record(&mut self, record: &Record, offset: u64) {
+        self.last_seq = record.seq;
+        if self.snapshots.wants(&record.key) {
+            self.snapshots.record(&record.key, record.seq, self.keydir.get(&record.key).copied());
+        }
+
+        let entry = HintEntry { offset, timestamp: record.timestamp, deleted: record.deleted };
+        self.active_index.insert(record.key.clone(), entry);
+        let segment = self.active_id;
+        Self::apply_to_keydir(&mut self.keydir, segment, &record.key, &entry);
+    }
+
+    // -> Look up the live record of a key. The lock is held to find it and take a handle to its segment,
+    //    which compaction can delete once it's released, the read itself happens outside.
+    pub fn get(storage: &Mutex<Self>, key: &str) -> Result<Option<Record>, DbError> {
+        let (entry, file, reader) = {
+            let storage = storage.lock().unwrap();
+            let entry = match storage.keydir.get(key) {
+                Some(entry) => *entry,
+                // the keydir holds every live key, a miss needs no segment read (and no Bloom filter)
+                None => return Ok(None),
+            };
+            let reader = storage.reader();
+            (entry, reader.open(entry.segment)?, reader)
+        };
+
+        reader.read_from(&file, &entry).map(Some)
+    }
+
+    // -> Live records whose key starts with prefix, ordered by key
+    pub fn scan_prefix(&self, prefix: &str) -> Result<Vec<Record>, DbError> {
+        let mut entries: Vec<(String, KeyDirEntry)> = self.keydir
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, entry)| (key.clone(), *entry))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let reader = self.reader();
+        entries.into_iter().map(|(_, entry)| reader.read(&entry)).collect()
+    }
+
+    // -> Reads records through the shared segment handles, with or without the lock
+    fn reader(&self) -> SegmentReader {
+        SegmentReader { base: self.base.clone(), cipher: self.cipher.clone(), handles: Arc::clone(&self.handles) }
+    }
+
+    // -> Read and verify the record (or every record of the batch) starting at offset, each with
+    //    the offset it starts at, along with the entry's stored length. None on a clean end of segment.
+    //    Reads are positional, so one handle can serve concurrent readers, and go no further than file_len.
+    fn read_entry(file: &File, file_len: u64, seg: &str, offset: u64, cipher: Option<&Cipher>) -> Result<Option<(Entry, u64)>, DbError> {
+        let corruption = || DbError::Corruption { segment: seg.to_string(), offset };
+        if offset >= file_len {
+            return Ok(None);
+        }
+
+        // first read header, records of older versions have shorter ones
+        let mut header = vec![0u8; HEADER_LEN.min((file_len - offset) as usize)];
+        read_exact_at(file, &mut header, offset)?;
+
+        let record_len = Record::record_len(&header).ok_or_else(corruption)?;
+        if offset + record_len as u64 > file_len {
+            return Err(corruption());
+        }
+
         // now read the rest
         let mut buf = vec![0u8; record_len];
-        seg_file.seek(SeekFrom::Start(offset))?;
-        seg_file.read_exact(&mut buf)?;
-        
-        Ok(Record::deserialize(&buf))
+        let read = header.len().min(record_len);
+        buf[..read].copy_from_slice(&header[..read]);
+        read_exact_at(file, &mut buf[read..], offset + read as u64)?;
+
+        let records = Record::decode_entry(&buf, cipher, Location::new(seg, offset)).ok_or_else(corruption)?;
+        let records = records.into_iter().map(|(at, record)| (offset + at as u64, record)).collect();
+        Ok(Some((records, record_len as u64)))
+    }
+}
+
+// -> What reading records needs, cloned out of the storage so reads can happen outside its lock
+struct SegmentReader {
+    base: PathBuf,
+    cipher: Option<Arc<Cipher>>,
+    handles: Arc<HandleCache>,
+}
+
+impl SegmentReader {
+    // -> Cached read handle of a segment
+    fn open(&self, segment: u32) -> Result<Arc<Handle>, DbError> {
+        let seg = segment_name(segment);
+        self.handles.get(&self.base.join("segments").join(&seg)).map_err(|e| DbError::from(e).for_segment(&seg))
+    }
+
+    // -> Record a keydir entry points at
+    fn read(&self, entry: &KeyDirEntry) -> Result<Record, DbError> {
+        let file = self.open(entry.segment)?;
+        self.read_from(&file, entry)
+    }
+
+    fn read_from(&self, handle: &Handle, entry: &KeyDirEntry) -> Result<Record, DbError> {
+        let seg = segment_name(entry.segment);
+        // records of a batch are indexed at their own offset, so this always reads a single one
+        Storage::read_entry(&handle.file, handle.len(), &seg, entry.offset, self.cipher.as_deref())?
+            .and_then(|(records, _)| records.into_iter().next())
+            .map(|(_, record)| record)
+            .ok_or(DbError::Corruption { segment: seg, offset: entry.offset })
+    }
+}
+
+// -> Segments pinned for a backup, built by `Storage::plan_backup`
+struct BackupJob {
+    base: PathBuf,
+    manifest: Manifest,
+    // length of the active segment when the backup started
+    active_len: u64,
+    cipher: Option<Arc<Cipher>>,
+}
+
+impl BackupJob {
+    // -> Copy the pinned segments and their hints, the manifest goes last so an interrupted backup can't be opened
+    fn run(&self, dest: &Path) -> Result<(), DbError> {
+        std::fs::create_dir_all(dest.join("segments"))?;
+        std::fs::create_dir_all(dest.join("index"))?;
+
+        for seg in &self.manifest.segments {
+            let src = self.base.join("segments").join(seg);
+            if *seg == self.manifest.active_segment {
+                copy_file(&src, &dest.join("segments").join(seg), Some(self.active_len))?;
+                continue;
+            }
+
+            copy_file(&src, &dest.join("segments").join(seg), None)?;
+            // a missing hint is rebuilt from its segment on open
+            let hint = hint_path(&self.base, seg);
+            if hint.exists() {
+                copy_file(&hint, &hint_path(dest, seg), None)?;
+            }
+        }
+
+        let data = serde_json::to_vec_pretty(&self.manifest)?;
+        let path = dest.join("manifest.json");
+        write_file_atomic(&path, &seal_file(self.cipher.as_deref(), data, &path))?;
+        Ok(())
+    }
+}
+
+// -> Sealed segments to merge into one, built by `Storage::plan_compaction`
+pub struct CompactionJob {
+    base: PathBuf,
+    // name reserved for the merged segment
+    name: String,
+    // oldest first
+    pub segments: Vec<String>,
+    compression: Compression,
+    cipher: Option<Arc<Cipher>>,
+}
+
+impl CompactionJob {
+    // -> Merge the segments and write the result as a new segment with its hint,
+    //    only touches files nobody else writes to so it needs no lock
+    pub fn run(&self) -> Result<SegIndex, DbError> {
+        let records = self.merge_segments()?;
+        Ok(self.write_compacted_records(records)?)
+    }
+
+    // -> File compaction if size exceeds threshold
+    //    Merges the segments into one map holding the latest record of every key by write sequence,
+    //    not by where it sits, so tombstones shadow older values wherever those are. Every sealed
+    //    segment takes part, the oldest included, so tombstones have nothing left to shadow and are dropped.
+    fn merge_segments(&self) -> Result<HashMap<String, Record>, DbError> {
+        let mut records: HashMap<String, Record> = HashMap::new();
+
+        for seg in &self.segments {
+            let file = OpenOptions::new()
+                .read(true)
+                // .open(format!("data/segments/{}", seg))?;
+                .open(self.base.join("segments").join(seg))
+                .map_err(|e| DbError::from(e).for_segment(seg))?;
+
+            let file_len = file.metadata()?.len();
+            let mut offset = 0;
+            while let Some((entry, len)) = Storage::read_entry(&file, file_len, seg, offset, self.cipher.as_deref())? {
+                offset += len;
+                for (_, record) in entry {
+                    if records.get(&record.key).is_none_or(|existing| record.supersedes(existing)) {
+                        records.insert(record.key.clone(), record);
+                    }
+                }
+            }
+        }
+
+        // expired records go the way of deleted ones
+        let now = now_secs();
+        for record in records.values_mut().filter(|r| !r.deleted && r.is_expired(now)) {
+            record.deleted = true;
+            record.value.clear();
+        }
+
+        records.retain(|_, record| !record.deleted);
+
+        Ok(records)
+    }
+
+    fn write_compacted_records(&self, records: HashMap<String, Record>) -> std::io::Result<SegIndex> {
+        // create temp file
+        // let mut tmp_log_file = File::create(format!("data/segments/{}.log.tmp", name))?;
+        let tmp_log_path = self.base.join("segments").join(format!("{}.tmp", self.name));
+        let mut tmp_log_file = File::create(&tmp_log_path)?;
+        let mut index = SegIndex::new();
+
+        let mut records: Vec<_> = records.into_iter().collect();
+        records.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut offset = 0;
+        for (key, record) in records {
+            // write record to tmp log
+            let record_bytes = record.encode(self.compression, self.cipher.as_deref(), Location::new(&self.name, offset));
+            tmp_log_file.write_all(&record_bytes)?;
+
+            index.insert(key, HintEntry { offset, timestamp: record.timestamp, deleted: record.deleted });
+            offset += record_bytes.len() as u64;
+        }
+
+        // atomic swap, the hint goes first so the segment never shows up without it
+        tmp_log_file.sync_all()?;
+        write_hint(&hint_path(&self.base, &self.name), &index, self.cipher.as_deref())?;
+        rename(tmp_log_path, self.base.join("segments").join(&self.name))?;
+
+        Ok(index)
     }
 }
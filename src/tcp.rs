@@ -1,10 +1,13 @@
-use std::{io::{BufRead, BufReader, Write}, net::{TcpListener, TcpStream}, sync::{Arc, Mutex}};
+use std::{io::{BufRead, BufReader, Write}, net::{TcpListener, TcpStream}};
 
-use crate::{api::Enso, repl::format_response};
+use enso::Enso;
+
+use crate::repl::format_response;
 
 pub const EOF_MARKER: &str = "<ENSO_EOF>";
 
-pub fn start_tcp(db: Arc<Mutex<Enso>>) {
+// -> Serve queries over TCP, every client gets its own session on db
+pub fn start_tcp(db: Enso) {
     std::thread::spawn(move || {
         let addr = "127.0.0.1:5432";
         let listener = TcpListener::bind(addr).expect("Failed to bind server");
@@ -13,8 +16,8 @@ pub fn start_tcp(db: Arc<Mutex<Enso>>) {
         for stream in listener.incoming() {
             match stream {
                 Ok(stream) => {
-                    let db = db.clone();
-                    std::thread::spawn(|| { handle_client(stream, db); });
+                    let session = db.clone().remote();
+                    std::thread::spawn(|| { handle_client(stream, session); });
                 }
                 Err(e) => {
                     eprintln!("[enso] TCP error: {:?}", e);
@@ -24,7 +27,7 @@ pub fn start_tcp(db: Arc<Mutex<Enso>>) {
     });
 }
 
-fn handle_client(stream: TcpStream, db: Arc<Mutex<Enso>>) {
+fn handle_client(stream: TcpStream, mut db: Enso) {
     let mut reader = BufReader::new(stream.try_clone().unwrap());
     let mut writer = stream;
 
@@ -40,21 +43,11 @@ fn handle_client(stream: TcpStream, db: Arc<Mutex<Enso>>) {
             continue;
         }
 
-        let mut response = {
-            let mut db = db.lock().unwrap();
-            match db.query(query) {
-                Ok(res) => format_response(&mut db, res),
-                Err(e) => Ok(format!("ERROR: {:?}\n", e)),
-            }
+        let mut response = match db.query(query).and_then(|res| format_response(&mut db, res)) {
+            Ok(response) => response,
+            Err(e) => format!("ERROR: {:?}\n", e),
         };
 
-        if let Err(e) = response {
-            eprintln!("[enso] Error: {:?}", e);
-            continue;
-        }
-
-        let mut response = response.unwrap();
-
         response.push('\n');
         response.push_str(EOF_MARKER);
         response.push('\n');
@@ -0,0 +1,47 @@
+use std::ops::{Deref, DerefMut};
+
+use crate::{api::Enso, error::DbError};
+
+// -> Open transaction on an `Enso`, from `Enso::transaction`. Everything `Enso` does is available
+//    through it; row writes are buffered and written as one atomic batch by `commit`.
+//    Dropping it without committing rolls the writes back.
+pub struct Transaction<'a> {
+    db: &'a mut Enso,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(db: &'a mut Enso) -> Self {
+        Self { db }
+    }
+
+    // -> Write the buffered rows, returns how many writes there were
+    pub fn commit(self) -> Result<u64, DbError> {
+        self.db.commit()
+    }
+
+    pub fn rollback(self) -> Result<(), DbError> {
+        self.db.rollback()
+    }
+}
+
+impl Deref for Transaction<'_> {
+    type Target = Enso;
+
+    fn deref(&self) -> &Enso {
+        self.db
+    }
+}
+
+impl DerefMut for Transaction<'_> {
+    fn deref_mut(&mut self) -> &mut Enso {
+        self.db
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if self.db.in_transaction() {
+            let _ = self.db.rollback();
+        }
+    }
+}
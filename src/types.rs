@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
 use serde::{Serialize, Deserialize};
 
@@ -6,31 +6,136 @@ use crate::error::DbError;
 
 // Storage engine
 
-pub type SegIndex = HashMap<String, u64>;
+// -> Latest record of a key within one segment, as kept in hint files
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HintEntry {
+    pub offset: u64,
+    pub timestamp: u64,
+    pub deleted: bool,
+}
+
+pub type SegIndex = HashMap<String, HintEntry>;
+
+// -> Where the live version of a key is stored
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyDirEntry {
+    pub segment: u32,
+    pub offset: u64,
+    pub timestamp: u64,
+}
 
-#[derive(Serialize, Deserialize, Debug)]
+// -> In-memory map of every live key across all segments
+pub type KeyDir = HashMap<String, KeyDirEntry>;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Manifest {
     pub active_segment: String,
     pub segments: Vec<String>,
     pub last_compaction: Option<String>,
+    // write sequence of the last record in a sealed segment
+    #[serde(default)]
+    pub last_seq: u64,
+}
+
+// -> Tables of the LSM backend, oldest first
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct LsmManifest {
+    pub tables: Vec<String>,
+    pub last_compaction: Option<String>,
+    // write sequence of the last record flushed to a table
+    #[serde(default)]
+    pub last_seq: u64,
+}
+
+// -> Logs kept for point-in-time recovery, oldest first (see archive.rs)
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ArchiveManifest {
+    pub logs: Vec<String>,
+}
+
+// -> What startup recovery had to repair
+#[derive(Debug, Default, Clone)]
+pub struct RecoveryReport {
+    // bytes of torn record(s) cut from the tail of the active segment
+    pub truncated_bytes: u64,
+    // segments whose hint file (or LSM tables whose Bloom filter) was missing or invalid and regenerated
+    pub rebuilt_indexes: Vec<String>,
+    // hint entries regenerated from the log
+    pub restored_entries: usize,
 }
 
+impl RecoveryReport {
+    pub fn is_clean(&self) -> bool {
+        self.truncated_bytes == 0 && self.rebuilt_indexes.is_empty()
+    }
+}
+
+// -> Counters for compactions run since open
+#[derive(Debug, Default, Clone)]
+pub struct CompactionStats {
+    // compactions that merged at least two segments
+    pub runs: u64,
+    pub segments_merged: u64,
+    // wall time of the last compaction, merging included
+    pub last_duration: Duration,
+    // time the last compaction held the storage lock, i.e. how long writers could be blocked by it
+    pub last_writer_blocked: Duration,
+    pub total_writer_blocked: Duration,
+    // why the last compaction failed, None if it succeeded. Background compactions have no caller
+    // to return their error to, this is where it shows up.
+    pub last_error: Option<String>,
+}
+
+impl CompactionStats {
+    pub fn record(&mut self, segments: usize, duration: Duration, writer_blocked: Duration) {
+        self.runs += 1;
+        self.segments_merged += segments as u64;
+        self.last_duration = duration;
+        self.last_writer_blocked = writer_blocked;
+        self.total_writer_blocked += writer_blocked;
+    }
+}
+
+// -> Bytes of records appended since open before and after compression
+#[derive(Debug, Default, Clone)]
+pub struct CompressionStats {
+    pub raw_bytes: u64,
+    pub stored_bytes: u64,
+}
+
+impl CompressionStats {
+    pub fn record(&mut self, raw: usize, stored: usize) {
+        self.raw_bytes += raw as u64;
+        self.stored_bytes += stored as u64;
+    }
+
+    // -> Stored size as a fraction of the raw size, 1.0 when nothing was written
+    pub fn ratio(&self) -> f64 {
+        if self.raw_bytes == 0 {
+            return 1.0;
+        }
+        self.stored_bytes as f64 / self.raw_bytes as f64
+    }
+}
 
 // User API
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct TableSchema {
     pub name: String,
     pub columns: Vec<Column>,
     pub primary_key: usize,
+    // seconds a row lives after it's inserted, None forever
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Column {
     pub name: String,
     pub dtype: DataType,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum DataType {
     Int,
     Float,
@@ -55,14 +160,14 @@ impl Column {
 
 impl Value {
     pub fn matches(&self, dtype: &DataType) -> bool {
-        match (self, dtype) {
-            (Value::Int(_), DataType::Int) => true,
-            (Value::Float(_), DataType::Float) => true,
-            (Value::Bool(_), DataType::Bool) => true,
-            (Value::String(_), DataType::String) => true,
-            (Value::Null, _) => true,
-            _ => false,
-        }
+        matches!(
+            (self, dtype),
+            (Value::Int(_), DataType::Int)
+                | (Value::Float(_), DataType::Float)
+                | (Value::Bool(_), DataType::Bool)
+                | (Value::String(_), DataType::String)
+                | (Value::Null, _)
+        )
     }
 
     pub fn to_key_bytes(&self) -> Result<Vec<u8>, DbError> {
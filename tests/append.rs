@@ -0,0 +1,79 @@
+// Runs on its own in this binary, the file size limit it sets applies to the whole process.
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use enso::{BackendKind, Engine};
+use common::{temp_db, config};
+
+const RLIMIT_FSIZE: i32 = 1;
+const SIGXFSZ: i32 = 25;
+const SIG_IGN: usize = 1;
+
+#[repr(C)]
+struct Rlimit {
+    cur: u64,
+    max: u64,
+}
+
+unsafe extern "C" {
+    fn getrlimit(resource: i32, limit: *mut Rlimit) -> i32;
+    fn setrlimit(resource: i32, limit: *const Rlimit) -> i32;
+    fn signal(signal: i32, handler: usize) -> usize;
+}
+
+// -> Cap the size any file of this process can grow to, writes past it fail with EFBIG
+fn limit_file_size(bytes: u64) {
+    unsafe {
+        let mut limit = Rlimit { cur: 0, max: 0 };
+        assert_eq!(getrlimit(RLIMIT_FSIZE, &mut limit), 0);
+        limit.cur = bytes.min(limit.max);
+        assert_eq!(setrlimit(RLIMIT_FSIZE, &limit), 0);
+    }
+}
+
+// -> File the next write of the database is appended to
+fn log_file(path: &Path, backend: BackendKind) -> PathBuf {
+    match backend {
+        BackendKind::Log => {
+            let mut segments: Vec<_> = std::fs::read_dir(path.join("segments")).unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "log"))
+                .collect();
+            segments.sort();
+            segments.pop().unwrap()
+        }
+        BackendKind::Lsm => path.join("lsm").join("wal.log"),
+    }
+}
+
+#[test]
+fn failed_append_leaves_no_bytes_behind() {
+    // the limit is reported as an error instead of killing the process
+    unsafe { signal(SIGXFSZ, SIG_IGN) };
+
+    for backend in [BackendKind::Log, BackendKind::Lsm] {
+        let path = temp_db(&format!("{:?}", backend));
+        let engine = Engine::open_with_config(&path, config(backend).segment_size(1024 * 1024)).unwrap();
+        engine.set_raw("before".to_string(), vec![1; 32]).unwrap();
+
+        // only the first few bytes of the next record fit
+        let file = log_file(&path, backend);
+        let len = std::fs::metadata(&file).unwrap().len();
+        limit_file_size(len + 8);
+        let failed = engine.set_raw("failed".to_string(), vec![2; 256]);
+        limit_file_size(u64::MAX);
+        assert!(failed.is_err(), "{:?}", backend);
+        assert_eq!(std::fs::metadata(&file).unwrap().len(), len, "{:?}", backend);
+
+        engine.set_raw("after".to_string(), vec![3; 32]).unwrap();
+        drop(engine);
+
+        let engine = Engine::open_with_config(&path, config(backend).segment_size(1024 * 1024)).unwrap();
+        assert_eq!(engine.get_raw("before").unwrap(), Some(vec![1; 32]), "{:?}", backend);
+        assert_eq!(engine.get_raw("failed").unwrap(), None, "{:?}", backend);
+        assert_eq!(engine.get_raw("after").unwrap(), Some(vec![3; 32]), "{:?}", backend);
+    }
+}
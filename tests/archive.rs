@@ -0,0 +1,129 @@
+mod common;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use enso::{row, schema, BackendKind, DbError, EncryptionKey, Engine, EngineConfig, Enso};
+use common::temp_db;
+
+fn config(backend: BackendKind) -> EngineConfig {
+    common::config(backend).archive(true)
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+// -> Current second, once every write made so far is strictly older than the next one
+fn checkpoint() -> u64 {
+    let at = now();
+    while now() == at {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    at
+}
+
+#[test]
+fn restore_to_brings_back_deleted_and_overwritten_keys() {
+    for backend in [BackendKind::Log, BackendKind::Lsm] {
+        let root = temp_db(&format!("{:?}", backend));
+        let engine = Engine::open_with_config(root.join("db"), config(backend)).unwrap();
+
+        for i in 0..300 {
+            engine.set_raw(format!("k{:04}", i), vec![1; 32]).unwrap();
+        }
+        let before = checkpoint();
+
+        for i in 0..300 {
+            if i % 2 == 0 {
+                engine.delete_raw(format!("k{:04}", i)).unwrap();
+            } else {
+                engine.set_raw(format!("k{:04}", i), vec![2; 32]).unwrap();
+            }
+        }
+        // compaction drops the old versions from the live segments, the archive keeps them
+        engine.compact().unwrap();
+        assert_eq!(engine.scan_prefix("k").unwrap().len(), 150);
+
+        engine.restore_to(before, root.join("restored"), None).unwrap();
+        let restored = Engine::open_with_config(root.join("restored"), config(backend)).unwrap();
+        assert_eq!(restored.scan_prefix("k").unwrap().len(), 300, "{:?}", backend);
+        assert_eq!(restored.get_raw("k0000").unwrap(), Some(vec![1; 32]));
+        assert_eq!(restored.get_raw("k0001").unwrap(), Some(vec![1; 32]));
+
+        // history survives a reopen
+        drop(engine);
+        let engine = Engine::open_with_config(root.join("db"), config(backend)).unwrap();
+        engine.restore_to(before, root.join("restored-again"), None).unwrap();
+        let restored = Engine::open_with_config(root.join("restored-again"), config(backend)).unwrap();
+        assert_eq!(restored.scan_prefix("k").unwrap().len(), 300);
+        assert!(matches!(engine.restore_to(before, root.join("restored"), None), Err(DbError::DatabaseExists)));
+    }
+}
+
+#[test]
+fn accidental_delete_is_recovered_with_schemas() {
+    let root = temp_db("delete");
+    let mut db = Enso::open_with_config(&root, "shop", EngineConfig::default().archive(true)).unwrap();
+    db.create_table("users", schema! {
+        id: Int => pk,
+        name: String,
+    }).unwrap();
+    db.insert(row![1, "amartya"]).unwrap();
+    db.insert(row![2, "bea"]).unwrap();
+    let before = checkpoint();
+
+    db.query("DELETE FROM users WHERE id = 2;").unwrap();
+    assert_eq!(db.select_all_from("users").unwrap().len(), 1);
+
+    db.restore_to(before, &root, "shop_before", None).unwrap();
+    let mut restored = Enso::open_at(&root, "shop_before").unwrap();
+    assert_eq!(restored.select_all_from("users").unwrap().len(), 2);
+}
+
+#[test]
+fn restore_to_writes_a_new_database_under_the_given_key() {
+    let root = temp_db("key");
+    let key = EncryptionKey::Passphrase("secret".to_string());
+    let other = EncryptionKey::Passphrase("other".to_string());
+    let mut db = Enso::open_with_config(&root, "shop", EngineConfig::default().archive(true).encryption(key.clone())).unwrap();
+    db.create_table("users", schema! {
+        id: Int => pk,
+        name: String,
+    }).unwrap();
+    db.insert(row![1, "amartya"]).unwrap();
+    let before = checkpoint();
+
+    db.restore_to(before, &root, "plain", None).unwrap();
+    assert_eq!(Enso::open_at(&root, "plain").unwrap().select_all_from("users").unwrap().len(), 1);
+
+    db.restore_to(before, &root, "rekeyed", Some(&other)).unwrap();
+    assert!(matches!(Enso::open_with_config(&root, "rekeyed", EngineConfig::default().encryption(key)), Err(DbError::WrongEncryptionKey)));
+    let mut restored = Enso::open_with_config(&root, "rekeyed", EngineConfig::default().encryption(other)).unwrap();
+    assert_eq!(restored.select_all_from("users").unwrap().len(), 1);
+}
+
+#[test]
+fn restore_to_writes_an_ordinary_database() {
+    let root = temp_db("ordinary");
+    let engine = Engine::open_with_config(root.join("db"), config(BackendKind::Log)).unwrap();
+    engine.set_raw("k".to_string(), vec![1]).unwrap();
+    let before = checkpoint();
+
+    // the restored database doesn't start an archive of its own
+    engine.restore_to(before, root.join("restored"), None).unwrap();
+    assert!(!root.join("restored").join("archive").exists());
+
+    let restored = Engine::open_with_config(root.join("restored"), common::config(BackendKind::Log)).unwrap();
+    assert_eq!(restored.get_raw("k").unwrap(), Some(vec![1]));
+    restored.set_raw("k".to_string(), vec![2]).unwrap();
+}
+
+#[test]
+fn restore_to_needs_the_archive() {
+    let root = temp_db("disabled");
+    let engine = Engine::open_with_config(root.join("db"), config(BackendKind::Log).archive(false)).unwrap();
+    engine.set_raw("k".to_string(), vec![1]).unwrap();
+
+    assert!(matches!(engine.restore_to(now(), root.join("restored"), None), Err(DbError::ArchiveDisabled)));
+    assert!(!root.join("restored").exists());
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use std::sync::Arc;
+
+use enso::{row, schema, BackendKind, DbError, Engine, Enso, QueryResult};
+use common::{temp_db, config};
+
+#[test]
+fn backup_taken_during_writes_and_compaction_restores_a_consistent_prefix() {
+    for backend in [BackendKind::Log, BackendKind::Lsm] {
+        let root = temp_db(&format!("online-{:?}", backend));
+        let engine = Arc::new(Engine::open_with_config(root.join("db"), config(backend)).unwrap());
+        for i in 0..300 {
+            engine.set_raw(format!("k{:05}", i), vec![1; 32]).unwrap();
+        }
+
+        let writer = {
+            let engine = Arc::clone(&engine);
+            std::thread::spawn(move || {
+                for i in 300..1500 {
+                    engine.set_raw(format!("k{:05}", i), vec![1; 32]).unwrap();
+                }
+            })
+        };
+        let compactor = {
+            let engine = Arc::clone(&engine);
+            std::thread::spawn(move || engine.compact().unwrap())
+        };
+        engine.backup(root.join("backup")).unwrap();
+        writer.join().unwrap();
+        compactor.join().unwrap();
+
+        Engine::restore(root.join("backup"), root.join("restored"), None).unwrap();
+        let restored = Engine::open_with_config(root.join("restored"), config(backend)).unwrap();
+        assert!(restored.recovery_report().is_clean());
+
+        // keys are written in order, so the backup holds every key up to some point and nothing after it
+        let keys: Vec<String> = restored.scan_prefix("k").unwrap().into_iter().map(|(k, _)| k).collect();
+        assert!(keys.len() >= 300, "{:?}: {}", backend, keys.len());
+        assert!(keys.iter().enumerate().all(|(i, k)| *k == format!("k{:05}", i)));
+    }
+}
+
+#[test]
+fn backup_statement_copies_tables_and_schemas() {
+    let root = temp_db("statement");
+    let backup = root.join("backup");
+
+    let mut db = Enso::open_at(&root, "shop").unwrap();
+    db.create_table("users", schema! {
+        id: Int => pk,
+        name: String,
+    }).unwrap();
+    db.insert(row![1, "amartya"]).unwrap();
+    db.insert(row![2, "bea"]).unwrap();
+
+    let stmt = format!("BACKUP TO '{}';", backup.display());
+    assert!(matches!(db.query(&stmt).unwrap(), QueryResult::Affected(0)));
+    assert!(matches!(db.query(&stmt), Err(DbError::DatabaseExists)));
+    db.delete_by_pk(2).unwrap();
+
+    Enso::restore(&backup, &root, "shop_restored", None).unwrap();
+    assert!(matches!(Enso::restore(&backup, &root, "shop", None), Err(DbError::DatabaseExists)));
+
+    let mut restored = Enso::open_at(&root, "shop_restored").unwrap();
+    assert_eq!(restored.select_all_from("users").unwrap().len(), 2);
+    assert_eq!(db.select_all_from("users").unwrap().len(), 1);
+}
+
+#[test]
+fn damaged_backup_is_not_restored() {
+    let root = temp_db("damaged");
+    let engine = Engine::open_with_config(root.join("db"), config(BackendKind::Log)).unwrap();
+    for i in 0..50 {
+        engine.set_raw(format!("k{:03}", i), vec![7; 32]).unwrap();
+    }
+    engine.backup(root.join("backup")).unwrap();
+
+    // flip a byte inside a record's value
+    let segment = root.join("backup").join("segments").join("enso-0001.log");
+    let mut data = std::fs::read(&segment).unwrap();
+    data[100] ^= 0xff;
+    std::fs::write(&segment, data).unwrap();
+
+    let result = Engine::restore(root.join("backup"), root.join("restored"), None);
+    assert!(matches!(result, Err(DbError::Corruption { .. })), "{:?}", result);
+    assert!(!root.join("restored").exists());
+    assert!(!root.join("restored.restore").exists());
+}
+
+#[test]
+fn in_memory_databases_cannot_be_backed_up() {
+    let root = temp_db("memory");
+    let db = Enso::in_memory("test_db").unwrap();
+    assert!(matches!(db.backup(root.join("backup")), Err(DbError::NotPersistent)));
+    assert!(!root.join("backup").exists());
+}
+
+#[test]
+fn remote_sessions_cannot_back_up() {
+    let root = temp_db("remote");
+    let backup = root.join("backup");
+
+    let mut db = Enso::open_at(&root, "shop").unwrap();
+    db.create_table("users", schema! {
+        id: Int => pk,
+        name: String,
+    }).unwrap();
+    db.insert(row![1, "amartya"]).unwrap();
+
+    // a client over the network could otherwise write wherever the server can
+    let stmt = format!("BACKUP TO '{}';", backup.display());
+    let mut remote = db.clone().remote();
+    assert!(matches!(remote.query(&stmt), Err(DbError::NotAllowed { statement }) if statement == "BACKUP"));
+    assert!(!backup.exists());
+    assert_eq!(remote.clone().select_all_from("users").unwrap().len(), 1);
+
+    assert!(matches!(db.query(&stmt).unwrap(), QueryResult::Affected(0)));
+    assert!(backup.exists());
+}
@@ -0,0 +1,48 @@
+// Helpers shared by the integration tests, each test file pulls them in with `mod common;`
+// and not every file uses all of them.
+#![allow(dead_code)]
+
+use std::{ops::Deref, path::{Path, PathBuf}};
+
+use enso::{BackendKind, CompactionPolicy, EngineConfig};
+
+// -> Directory under the system temp dir that is deleted again when dropped.
+//    Declare it before the engines opened in it, so they're closed first.
+pub struct TempDir(PathBuf);
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+// -> Fresh directory for a test database, name must be unique within the test binary
+pub fn temp_db(name: &str) -> TempDir {
+    let exe = std::env::current_exe().ok();
+    let binary = exe.as_deref().and_then(Path::file_stem).and_then(|s| s.to_str()).unwrap_or("test");
+    let dir = std::env::temp_dir().join(format!("enso-test-{}-{}-{}", binary, name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    TempDir(dir)
+}
+
+// -> Small segments and manual compaction, so tests decide when segments are sealed and merged
+pub fn config(backend: BackendKind) -> EngineConfig {
+    EngineConfig::default()
+        .backend(backend)
+        .segment_size(4 * 1024)
+        .compaction(CompactionPolicy::Manual)
+}
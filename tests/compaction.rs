@@ -0,0 +1,196 @@
+mod common;
+
+use std::{sync::Arc, time::{Duration, Instant}};
+
+use enso::{BackendKind, CompactionPolicy, Engine, EngineConfig};
+use common::temp_db;
+
+// tiny segments so every few writes seal a segment, compaction only when asked
+fn config() -> EngineConfig {
+    common::config(BackendKind::Log).segment_size(128)
+}
+
+#[test]
+fn deleted_rows_stay_deleted_after_compaction() {
+    let dir = temp_db("deleted-rows");
+    let engine = Engine::open_with_config(&dir, config()).unwrap();
+
+    for i in 0..20 {
+        engine.set_raw(format!("users:{}", i), vec![i as u8; 16]).unwrap();
+    }
+    for i in 0..10 {
+        engine.delete_raw(format!("users:{}", i)).unwrap();
+    }
+    // seal the segment holding the last tombstones
+    engine.set_raw("other:0".to_string(), vec![0; 64]).unwrap();
+
+    engine.compact().unwrap();
+    for i in 0..10 {
+        assert_eq!(engine.get_raw(&format!("users:{}", i)).unwrap(), None);
+    }
+    assert_eq!(engine.scan_prefix("users:").unwrap().len(), 10);
+
+    drop(engine);
+    let engine = Engine::open_with_config(&dir, config()).unwrap();
+    for i in 0..10 {
+        assert_eq!(engine.get_raw(&format!("users:{}", i)).unwrap(), None);
+    }
+    assert_eq!(engine.scan_prefix("users:").unwrap().len(), 10);
+}
+
+#[test]
+fn newest_value_wins_after_compaction() {
+    let dir = temp_db("newest-value");
+    let engine = Engine::open_with_config(&dir, config()).unwrap();
+
+    for round in 0..5u8 {
+        for i in 0..4 {
+            engine.set_raw(format!("k{}", i), vec![round; 16]).unwrap();
+        }
+    }
+
+    engine.compact().unwrap();
+    for i in 0..4 {
+        assert_eq!(engine.get_raw(&format!("k{}", i)).unwrap(), Some(vec![4; 16]));
+    }
+
+    drop(engine);
+    let engine = Engine::open_with_config(&dir, config()).unwrap();
+    for i in 0..4 {
+        assert_eq!(engine.get_raw(&format!("k{}", i)).unwrap(), Some(vec![4; 16]));
+    }
+}
+
+#[test]
+fn delete_after_compaction_shadows_compacted_value() {
+    let dir = temp_db("delete-after");
+    let engine = Engine::open_with_config(&dir, config()).unwrap();
+
+    for i in 0..10 {
+        engine.set_raw(format!("k{}", i), vec![1; 16]).unwrap();
+    }
+    engine.compact().unwrap();
+
+    engine.delete_raw("k3".to_string()).unwrap();
+    assert_eq!(engine.get_raw("k3").unwrap(), None);
+
+    // the tombstone now sits in a segment newer than the compacted one
+    for i in 10..20 {
+        engine.set_raw(format!("k{}", i), vec![1; 16]).unwrap();
+    }
+    engine.compact().unwrap();
+
+    drop(engine);
+    let engine = Engine::open_with_config(&dir, config()).unwrap();
+    assert_eq!(engine.get_raw("k3").unwrap(), None);
+    assert_eq!(engine.get_raw("k4").unwrap(), Some(vec![1; 16]));
+}
+
+#[test]
+fn reinserted_row_survives_compaction() {
+    let dir = temp_db("reinserted");
+    let engine = Engine::open_with_config(&dir, config()).unwrap();
+
+    engine.set_raw("k".to_string(), vec![1; 16]).unwrap();
+    engine.delete_raw("k".to_string()).unwrap();
+    engine.set_raw("k".to_string(), vec![2; 16]).unwrap();
+    for i in 0..10 {
+        engine.set_raw(format!("filler{}", i), vec![0; 16]).unwrap();
+    }
+
+    engine.compact().unwrap();
+    assert_eq!(engine.get_raw("k").unwrap(), Some(vec![2; 16]));
+
+    drop(engine);
+    let engine = Engine::open_with_config(&dir, config()).unwrap();
+    assert_eq!(engine.get_raw("k").unwrap(), Some(vec![2; 16]));
+}
+
+#[test]
+fn writes_during_compaction_are_kept() {
+    let dir = temp_db("concurrent-writes");
+    let engine = Arc::new(Engine::open_with_config(&dir, config()).unwrap());
+
+    for i in 0..50 {
+        engine.set_raw(format!("old{}", i), vec![1; 16]).unwrap();
+    }
+
+    let writer = {
+        let engine = Arc::clone(&engine);
+        std::thread::spawn(move || {
+            for i in 0..50 {
+                engine.set_raw(format!("new{}", i), vec![2; 16]).unwrap();
+                engine.delete_raw(format!("old{}", i % 10)).unwrap();
+            }
+        })
+    };
+    engine.compact().unwrap();
+    writer.join().unwrap();
+
+    let stats = engine.compaction_stats();
+    assert_eq!(stats.runs, 1);
+    assert!(stats.last_writer_blocked <= stats.last_duration);
+
+    let check = |engine: &Engine| {
+        assert_eq!(engine.scan_prefix("new").unwrap().len(), 50);
+        assert_eq!(engine.scan_prefix("old").unwrap().len(), 40);
+        assert_eq!(engine.get_raw("old3").unwrap(), None);
+    };
+    check(&engine);
+    drop(engine);
+    check(&Engine::open_with_config(&dir, config()).unwrap());
+}
+
+#[test]
+fn background_compaction_failure_is_reported_in_stats() {
+    let path = temp_db("failed");
+    let config = common::config(BackendKind::Log).compaction(CompactionPolicy::Automatic).max_segments(3);
+    let engine = Engine::open_with_config(&path, config).unwrap();
+    let mut i = 0;
+    while engine.storage.segment_count() < 3 {
+        engine.set_raw(format!("k{:04}", i), vec![1; 32]).unwrap();
+        i += 1;
+    }
+    assert!(engine.compaction_stats().last_error.is_none());
+
+    // the next rotation starts a compaction that can't read this segment
+    std::fs::remove_file(path.join("segments").join("enso-0001.log")).unwrap();
+    while engine.storage.segment_count() == 3 {
+        engine.set_raw(format!("k{:04}", i), vec![1; 32]).unwrap();
+        i += 1;
+    }
+
+    let started = Instant::now();
+    while engine.compaction_stats().last_error.is_none() && started.elapsed() < Duration::from_secs(5) {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let error = engine.compaction_stats().last_error.expect("compaction error");
+    assert!(error.contains("enso-0001.log"), "{}", error);
+}
+
+#[test]
+fn merge_keeps_the_latest_write_whatever_the_segment_order() {
+    let dir = temp_db("write-order");
+    let engine = Engine::open_with_config(&dir, config()).unwrap();
+    engine.set_raw("k".to_string(), vec![1; 64]).unwrap();
+    engine.set_raw("k".to_string(), vec![2; 64]).unwrap();
+    engine.delete_raw("gone".to_string()).unwrap();
+    engine.set_raw("x".to_string(), vec![0; 64]).unwrap();
+    drop(engine);
+
+    // list the segment holding the newer write first, as if it were the older one
+    let manifest = dir.join("manifest.json");
+    let data = std::fs::read_to_string(&manifest).unwrap();
+    assert!(data.contains("\"enso-0001.log\",\n    \"enso-0002.log\""), "{}", data);
+    let swapped = data.replacen("\"enso-0001.log\",\n    \"enso-0002.log\"", "\"enso-0002.log\",\n    \"enso-0001.log\"", 1);
+    std::fs::write(&manifest, swapped).unwrap();
+
+    let engine = Engine::open_with_config(&dir, config()).unwrap();
+    engine.compact().unwrap();
+    assert_eq!(engine.get_raw("k").unwrap(), Some(vec![2; 64]));
+    drop(engine);
+
+    let engine = Engine::open_with_config(&dir, config()).unwrap();
+    assert_eq!(engine.get_raw("k").unwrap(), Some(vec![2; 64]));
+    assert_eq!(engine.get_raw("gone").unwrap(), None);
+}
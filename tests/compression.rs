@@ -0,0 +1,91 @@
+mod common;
+
+use std::io::Write;
+
+use enso::{BackendKind, Compression, Engine, EngineConfig};
+use common::temp_db;
+
+fn config(backend: BackendKind, compression: Compression) -> EngineConfig {
+    common::config(backend).segment_size(16 * 1024).compression(compression)
+}
+
+fn row(i: usize) -> Vec<u8> {
+    format!("{{\"id\": {}, \"name\": \"user number {}\", \"bio\": \"{}\"}}", i, i, "likes databases ".repeat(8)).into_bytes()
+}
+
+#[test]
+fn compressed_values_read_back_on_every_backend() {
+    for backend in [BackendKind::Log, BackendKind::Lsm] {
+        for compression in [Compression::Lz4, Compression::Zstd] {
+            let dir = temp_db(&format!("{:?}-{:?}", backend, compression));
+            let engine = Engine::open_with_config(&dir, config(backend, compression)).unwrap();
+
+            for i in 0..200 {
+                engine.set_raw(format!("users:{:04}", i), row(i)).unwrap();
+            }
+            engine.set_raw("tiny".to_string(), vec![1]).unwrap();
+
+            let stats = engine.compression_stats();
+            assert!(stats.ratio() < 0.6, "{:?} {:?}: {:?}", backend, compression, stats);
+
+            engine.compact().unwrap();
+            drop(engine);
+
+            let engine = Engine::open_with_config(&dir, config(backend, compression)).unwrap();
+            assert_eq!(engine.get_raw("users:0042").unwrap(), Some(row(42)));
+            assert_eq!(engine.get_raw("tiny").unwrap(), Some(vec![1]));
+            assert_eq!(engine.scan_prefix("users:").unwrap().len(), 200);
+        }
+    }
+}
+
+#[test]
+fn switching_codecs_keeps_old_records_readable() {
+    let dir = temp_db("switch");
+    let engine = Engine::open_with_config(&dir, config(BackendKind::Log, Compression::None)).unwrap();
+    engine.set_raw("plain".to_string(), row(1)).unwrap();
+    assert_eq!(engine.compression_stats().ratio(), 1.0);
+    drop(engine);
+
+    let engine = Engine::open_with_config(&dir, config(BackendKind::Log, Compression::Zstd)).unwrap();
+    engine.set_raw("packed".to_string(), row(2)).unwrap();
+    assert_eq!(engine.get_raw("plain").unwrap(), Some(row(1)));
+    assert_eq!(engine.get_raw("packed").unwrap(), Some(row(2)));
+}
+
+#[test]
+fn records_of_the_original_format_are_still_read() {
+    let dir = temp_db("original");
+    drop(Engine::open_with_config(&dir, config(BackendKind::Log, Compression::None)).unwrap());
+
+    // [key_len][val_len][timestamp][deleted][key][value], no version byte and no checksum
+    let original = |key: &[u8], value: &[u8], deleted: bool| {
+        let mut record = (key.len() as u32).to_be_bytes().to_vec();
+        record.extend_from_slice(&(value.len() as u32).to_be_bytes());
+        record.extend_from_slice(&7u64.to_be_bytes());
+        record.push(deleted as u8);
+        record.extend_from_slice(key);
+        record.extend_from_slice(value);
+        record
+    };
+    let mut records = original(b"users:1", b"legacy row", false);
+    records.extend(original(b"users:2", b"deleted row", false));
+    records.extend(original(b"users:2", b"", true));
+
+    let segment = dir.join("segments").join("enso-0001.log");
+    std::fs::OpenOptions::new().append(true).open(segment).unwrap().write_all(&records).unwrap();
+
+    let engine = Engine::open_with_config(&dir, config(BackendKind::Log, Compression::Lz4)).unwrap();
+    assert!(engine.recovery_report().is_clean());
+    assert_eq!(engine.get_raw("users:1").unwrap(), Some(b"legacy row".to_vec()));
+    assert_eq!(engine.get_raw("users:2").unwrap(), None);
+
+    // new records go after them in the same segment
+    engine.set_raw("users:3".to_string(), row(3)).unwrap();
+    drop(engine);
+    let engine = Engine::open_with_config(&dir, config(BackendKind::Log, Compression::Lz4)).unwrap();
+    assert!(engine.recovery_report().is_clean());
+    assert_eq!(engine.get_raw("users:1").unwrap(), Some(b"legacy row".to_vec()));
+    assert_eq!(engine.get_raw("users:3").unwrap(), Some(row(3)));
+}
+
@@ -0,0 +1,132 @@
+mod common;
+
+use std::{sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, Arc}, time::Duration};
+
+use enso::{BackendKind, Durability, Engine, GroupCommit};
+use common::temp_db;
+
+const WRITERS: usize = 8;
+
+// -> Writers that each append one record and wait for it to be synced, `sync` is what the leader runs
+fn wait_for_writers(commit: &Arc<GroupCommit>, appended: &Arc<AtomicU64>, sync: impl Fn() -> std::io::Result<u64> + Send + Sync + 'static) -> Vec<std::io::Result<()>> {
+    let sync = Arc::new(sync);
+    let writers: Vec<_> = (0..WRITERS)
+        .map(|_| {
+            let (commit, appended, sync) = (Arc::clone(commit), Arc::clone(appended), Arc::clone(&sync));
+            std::thread::spawn(move || {
+                let seq = appended.fetch_add(1, Ordering::SeqCst) + 1;
+                // the batch is full once every writer joined, the interval is only a fallback
+                commit.wait(seq, || sync(), Duration::from_secs(10), WRITERS)
+            })
+        })
+        .collect();
+    writers.into_iter().map(|w| w.join().unwrap()).collect()
+}
+
+#[test]
+fn concurrent_writers_share_one_fsync() {
+    let commit = Arc::new(GroupCommit::default());
+    let appended = Arc::new(AtomicU64::new(0));
+    let syncs = Arc::new(AtomicUsize::new(0));
+
+    let (covered, counted) = (Arc::clone(&appended), Arc::clone(&syncs));
+    let results = wait_for_writers(&commit, &appended, move || {
+        counted.fetch_add(1, Ordering::SeqCst);
+        Ok(covered.load(Ordering::SeqCst))
+    });
+
+    assert!(results.iter().all(|r| r.is_ok()));
+    assert_eq!(syncs.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn followers_sync_themselves_after_the_leader_fails() {
+    let commit = Arc::new(GroupCommit::default());
+    let appended = Arc::new(AtomicU64::new(0));
+    let syncs = Arc::new(AtomicUsize::new(0));
+
+    // the first sync fails, the writer that issued it gets the error
+    let (covered, counted) = (Arc::clone(&appended), Arc::clone(&syncs));
+    let results = wait_for_writers(&commit, &appended, move || {
+        if counted.fetch_add(1, Ordering::SeqCst) == 0 {
+            return Err(std::io::Error::other("sync failed"));
+        }
+        Ok(covered.load(Ordering::SeqCst))
+    });
+
+    assert_eq!(results.iter().filter(|r| r.is_err()).count(), 1);
+    // one of the followers took over and synced for the rest
+    assert_eq!(syncs.load(Ordering::SeqCst), 2);
+
+    // a later writer isn't held up by the failed sync
+    let counted = Arc::clone(&syncs);
+    commit.wait(WRITERS as u64 + 1, move || {
+        counted.fetch_add(1, Ordering::SeqCst);
+        Ok(WRITERS as u64 + 1)
+    }, Duration::ZERO, 1).unwrap();
+    assert_eq!(syncs.load(Ordering::SeqCst), 3);
+}
+
+// -> Write from several threads at once with the given durability, then check every write after a reopen
+fn writes_survive_reopen(name: &str, durability: Durability) {
+    for backend in [BackendKind::Log, BackendKind::Lsm] {
+        let path = temp_db(&format!("{}-{:?}", name, backend));
+        let config = || common::config(backend).durability(durability);
+        let engine = Arc::new(Engine::open_with_config(&path, config()).unwrap());
+
+        let writers: Vec<_> = (0..4)
+            .map(|t| {
+                let engine = Arc::clone(&engine);
+                std::thread::spawn(move || {
+                    for i in 0..50 {
+                        engine.set_raw(format!("k{}-{:02}", t, i), vec![t as u8; 16]).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        drop(engine);
+
+        let engine = Engine::open_with_config(&path, config()).unwrap();
+        assert!(engine.recovery_report().is_clean(), "{:?}", backend);
+        assert_eq!(engine.scan_prefix("k").unwrap().len(), 200, "{:?}", backend);
+        assert_eq!(engine.get_raw("k3-49").unwrap(), Some(vec![3; 16]), "{:?}", backend);
+    }
+}
+
+#[test]
+fn flush_every_write_keeps_every_write() {
+    writes_survive_reopen("flush", Durability::FlushEveryWrite);
+}
+
+#[test]
+fn group_commit_keeps_every_write() {
+    writes_survive_reopen("group", Durability::GroupCommit { interval: Duration::from_millis(2), max_batch: 4 });
+}
+
+#[test]
+fn manifest_rewrite_cut_short_leaves_the_old_one() {
+    let config = || common::config(BackendKind::Log);
+    let path = temp_db("manifest");
+    let engine = Engine::open_with_config(&path, config()).unwrap();
+    for i in 0..200 {
+        engine.set_raw(format!("k{:04}", i), vec![1; 32]).unwrap();
+    }
+    drop(engine);
+    let manifest = std::fs::read(path.join("manifest.json")).unwrap();
+
+    // a crash while writing the new manifest only leaves its temp file behind
+    std::fs::write(path.join("manifest.json.tmp"), &manifest[..manifest.len() / 2]).unwrap();
+    let engine = Engine::open_with_config(&path, config()).unwrap();
+    assert_eq!(std::fs::read(path.join("manifest.json")).unwrap(), manifest);
+    assert_eq!(engine.scan_prefix("k").unwrap().len(), 200);
+
+    // the next rewrite replaces the manifest whole
+    engine.compact().unwrap();
+    drop(engine);
+    assert!(!path.join("manifest.json.tmp").exists());
+    let engine = Engine::open_with_config(&path, config()).unwrap();
+    assert_eq!(engine.scan_prefix("k").unwrap().len(), 200);
+}
@@ -0,0 +1,172 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use enso::{row, schema, BackendKind, DbError, EncryptionKey, Engine, EngineConfig, Enso, Value};
+use common::{temp_db, config};
+
+fn key_file(dir: &Path, name: &str, byte: u8) -> EncryptionKey {
+    std::fs::create_dir_all(dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, [byte; 32]).unwrap();
+    EncryptionKey::KeyFile(path)
+}
+
+// -> Every file under dir, recursively
+fn files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(self::files(&path));
+        } else {
+            files.push(path);
+        }
+    }
+    files
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+#[test]
+fn encrypted_data_reads_back_and_is_unreadable_at_rest() {
+    for backend in [BackendKind::Log, BackendKind::Lsm] {
+        let root = temp_db(&format!("{:?}", backend));
+        let dir = root.join("db");
+        let key = key_file(&root, "enso.key", 7);
+
+        let engine = Engine::open_with_config(&dir, config(backend).encryption(key.clone())).unwrap();
+        for i in 0..200 {
+            engine.set_raw(format!("secret:{:04}", i), b"top secret value".to_vec()).unwrap();
+        }
+        engine.delete_raw("secret:0001".to_string()).unwrap();
+        engine.compact().unwrap();
+        drop(engine);
+
+        let files = files(&dir);
+        assert!(files.len() > 2, "{:?}", files);
+        for file in &files {
+            let data = std::fs::read(file).unwrap();
+            assert!(!contains(&data, b"secret:"), "{:?} leaks keys", file);
+            assert!(!contains(&data, b"top secret"), "{:?} leaks values", file);
+        }
+
+        let engine = Engine::open_with_config(&dir, config(backend).encryption(key)).unwrap();
+        assert!(engine.recovery_report().is_clean());
+        assert_eq!(engine.get_raw("secret:0042").unwrap(), Some(b"top secret value".to_vec()));
+        assert_eq!(engine.get_raw("secret:0001").unwrap(), None);
+        assert_eq!(engine.scan_prefix("secret:").unwrap().len(), 199);
+    }
+}
+
+#[test]
+fn opening_without_the_right_key_fails() {
+    let root = temp_db("keys");
+    let dir = root.join("db");
+    let key = key_file(&root, "enso.key", 1);
+
+    let engine = Engine::open_with_config(&dir, config(BackendKind::Log).encryption(key)).unwrap();
+    engine.set_raw("k".to_string(), vec![1]).unwrap();
+    drop(engine);
+
+    let result = Engine::open_with_config(&dir, config(BackendKind::Log));
+    assert!(matches!(result, Err(DbError::EncryptionKeyRequired)));
+
+    let wrong = key_file(&root, "wrong.key", 2);
+    let result = Engine::open_with_config(&dir, config(BackendKind::Log).encryption(wrong));
+    assert!(matches!(result, Err(DbError::WrongEncryptionKey)));
+
+    let short = root.join("short.key");
+    std::fs::write(&short, [1; 16]).unwrap();
+    let result = Engine::open_with_config(&dir, config(BackendKind::Log).encryption(EncryptionKey::KeyFile(short)));
+    assert!(matches!(result, Err(DbError::WrongEncryptionKey)));
+}
+
+#[test]
+fn key_is_refused_for_a_plaintext_database() {
+    let root = temp_db("plaintext");
+    let dir = root.join("db");
+    drop(Engine::open_with_config(&dir, config(BackendKind::Log)).unwrap());
+
+    let key = key_file(&root, "enso.key", 3);
+    let result = Engine::open_with_config(&dir, config(BackendKind::Log).encryption(key));
+    assert!(matches!(result, Err(DbError::NotEncrypted)));
+}
+
+#[test]
+fn passphrase_encrypts_tables_and_schemas() {
+    let root = temp_db("passphrase");
+    let key = EncryptionKey::Passphrase("correct horse battery staple".to_string());
+    let config = EngineConfig::default().encryption(key);
+
+    let mut db = Enso::open_with_config(&root, "shop", config.clone()).unwrap();
+    db.create_table("customers", schema! {
+        id: Int => pk,
+        name: String,
+    }).unwrap();
+    db.insert(row![1, "ada lovelace"]).unwrap();
+    drop(db);
+
+    for file in files(&root.join("shop")) {
+        let data = std::fs::read(&file).unwrap();
+        assert!(!contains(&data, b"customers"), "{:?} leaks the schema", file);
+        assert!(!contains(&data, b"lovelace"), "{:?} leaks rows", file);
+    }
+
+    assert!(matches!(Enso::open_at(&root, "shop"), Err(DbError::EncryptionKeyRequired)));
+
+    let mut db = Enso::open_with_config(&root, "shop", config).unwrap();
+    let customer = db.select_by_pk_from("customers", 1).unwrap().unwrap();
+    assert!(matches!(&customer[1], Value::String(name) if name == "ada lovelace"));
+}
+
+#[test]
+fn sealed_records_only_open_where_they_were_written() {
+    let root = temp_db("moved-record");
+    let dir = root.join("db");
+    let key = key_file(&root, "enso.key", 3);
+    let config = config(BackendKind::Log).segment_size(1024 * 1024).encryption(key);
+
+    let engine = Engine::open_with_config(&dir, config.clone()).unwrap();
+    engine.set_raw("k".to_string(), vec![1; 32]).unwrap();
+    engine.set_raw("k".to_string(), vec![2; 32]).unwrap();
+    engine.set_raw("other".to_string(), vec![3; 32]).unwrap();
+    drop(engine);
+
+    // roll k back by copying its first version over the second
+    let segment = dir.join("segments").join("enso-0001.log");
+    let mut bytes = std::fs::read(&segment).unwrap();
+    // both frames of k are the length of other's less the 4 bytes its key is longer
+    let len = (bytes.len() - 4) / 3;
+    bytes.copy_within(..len, len);
+    std::fs::write(&segment, &bytes).unwrap();
+
+    match Engine::open_with_config(&dir, config) {
+        Err(DbError::Corruption { segment, offset }) => assert_eq!((segment.as_str(), offset), ("enso-0001.log", len as u64)),
+        other => panic!("expected corruption, got {:?}", other.err()),
+    }
+}
+
+#[test]
+fn sealed_files_only_open_under_their_own_name() {
+    let root = temp_db("moved-file");
+    let dir = root.join("db");
+    let key = key_file(&root, "enso.key", 4);
+    let config = config(BackendKind::Log).encryption(key);
+
+    let engine = Engine::open_with_config(&dir, config.clone()).unwrap();
+    for i in 0..200 {
+        engine.set_raw(format!("k{:04}", i), vec![1; 32]).unwrap();
+    }
+    drop(engine);
+
+    // the hint of one segment put in place of another's is rejected and rebuilt
+    let index = dir.join("index");
+    std::fs::copy(index.join("enso-0001.hint"), index.join("enso-0002.hint")).unwrap();
+
+    let engine = Engine::open_with_config(&dir, config).unwrap();
+    assert_eq!(engine.recovery_report().rebuilt_indexes, vec!["enso-0002.log".to_string()]);
+    assert_eq!(engine.scan_prefix("k").unwrap().len(), 200);
+}
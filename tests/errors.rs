@@ -0,0 +1,111 @@
+mod common;
+
+use std::{fs::OpenOptions, io::{ErrorKind, Write}};
+
+use enso::{BackendKind, DbError, Engine};
+use common::{temp_db, config};
+
+#[test]
+fn corrupted_manifest_is_reported_on_open() {
+    for backend in [BackendKind::Log, BackendKind::Lsm] {
+        let path = temp_db(&format!("manifest-{:?}", backend));
+        let engine = Engine::open_with_config(&path, config(backend)).unwrap();
+        engine.set_raw("k".to_string(), vec![1]).unwrap();
+        drop(engine);
+
+        let manifest = match backend {
+            BackendKind::Log => path.join("manifest.json"),
+            BackendKind::Lsm => path.join("lsm").join("manifest.json"),
+        };
+        std::fs::write(&manifest, b"{ not json").unwrap();
+
+        let result = Engine::open_with_config(&path, config(backend));
+        assert!(matches!(result, Err(DbError::CorruptedManifest { .. })), "{:?}", backend);
+    }
+}
+
+#[test]
+fn manifest_naming_an_invalid_segment_is_corrupted() {
+    for backend in [BackendKind::Log, BackendKind::Lsm] {
+        let path = temp_db(&format!("names-{:?}", backend));
+        let engine = Engine::open_with_config(&path, config(backend)).unwrap();
+        // enough to flush the first table
+        for i in 0..200 {
+            engine.set_raw(format!("k{:04}", i), vec![1; 32]).unwrap();
+        }
+        drop(engine);
+
+        let (manifest, name) = match backend {
+            BackendKind::Log => (path.join("manifest.json"), "enso-0001.log"),
+            BackendKind::Lsm => (path.join("lsm").join("manifest.json"), "sst-000001.sst"),
+        };
+        let data = std::fs::read_to_string(&manifest).unwrap();
+        std::fs::write(&manifest, data.replace(name, "first-segment")).unwrap();
+
+        let result = Engine::open_with_config(&path, config(backend));
+        assert!(matches!(result, Err(DbError::CorruptedManifest { .. })), "{:?}", backend);
+    }
+}
+
+#[test]
+fn missing_segment_is_reported_on_open() {
+    for backend in [BackendKind::Log, BackendKind::Lsm] {
+        let path = temp_db(&format!("segment-{:?}", backend));
+        let engine = Engine::open_with_config(&path, config(backend)).unwrap();
+        // enough to seal the first segment, or flush the first table
+        for i in 0..200 {
+            engine.set_raw(format!("k{:04}", i), vec![1; 32]).unwrap();
+        }
+        drop(engine);
+
+        let (file, name) = match backend {
+            BackendKind::Log => (path.join("segments").join("enso-0001.log"), "enso-0001.log"),
+            BackendKind::Lsm => (path.join("lsm").join("sst-000001.sst"), "sst-000001.sst"),
+        };
+        std::fs::remove_file(&file).unwrap();
+
+        match Engine::open_with_config(&path, config(backend)) {
+            Err(DbError::MissingSegment { segment }) => assert_eq!(segment, name),
+            other => panic!("{:?}: expected a missing segment, got {:?}", backend, other.err()),
+        }
+    }
+}
+
+#[test]
+fn zero_filled_tail_is_truncated_on_open() {
+    for backend in [BackendKind::Log, BackendKind::Lsm] {
+        let path = temp_db(&format!("zeros-{:?}", backend));
+        let engine = Engine::open_with_config(&path, config(backend)).unwrap();
+        engine.set_raw("k".to_string(), vec![1]).unwrap();
+        drop(engine);
+
+        // the file grew before the crash, its data never made it
+        let log = match backend {
+            BackendKind::Log => path.join("segments").join("enso-0001.log"),
+            BackendKind::Lsm => path.join("lsm").join("wal.log"),
+        };
+        let mut file = OpenOptions::new().append(true).open(&log).unwrap();
+        file.write_all(&[0; 4096]).unwrap();
+        drop(file);
+
+        let engine = Engine::open_with_config(&path, config(backend)).unwrap();
+        assert_eq!(engine.recovery_report().truncated_bytes, 4096, "{:?}", backend);
+        assert_eq!(engine.get_raw("k").unwrap(), Some(vec![1]));
+        engine.set_raw("after".to_string(), vec![2]).unwrap();
+        drop(engine);
+
+        let engine = Engine::open_with_config(&path, config(backend)).unwrap();
+        assert!(engine.recovery_report().is_clean(), "{:?}", backend);
+        assert_eq!(engine.get_raw("after").unwrap(), Some(vec![2]));
+    }
+}
+
+#[test]
+fn io_errors_map_to_specific_variants() {
+    let full = DbError::from(std::io::Error::from(ErrorKind::StorageFull));
+    assert!(matches!(full, DbError::DiskFull));
+    let denied = DbError::from(std::io::Error::from(ErrorKind::PermissionDenied));
+    assert!(matches!(denied, DbError::PermissionDenied));
+    let other = DbError::from(std::io::Error::from(ErrorKind::UnexpectedEof));
+    assert!(matches!(other, DbError::Io(_)));
+}
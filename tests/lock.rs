@@ -0,0 +1,155 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use enso::{row, schema, BackendKind, DbError, EncryptionKey, Engine, EngineConfig, Enso};
+use common::{temp_db, config};
+
+#[test]
+fn second_writer_is_locked_out_until_the_first_closes() {
+    for backend in [BackendKind::Log, BackendKind::Lsm] {
+        let path = temp_db(&format!("writer-{:?}", backend));
+        let engine = Engine::open_with_config(&path, config(backend)).unwrap();
+        engine.set_raw("k".to_string(), vec![1]).unwrap();
+
+        let second = Engine::open_with_config(&path, config(backend));
+        assert!(matches!(second, Err(DbError::DatabaseLocked)), "{:?}", backend);
+
+        drop(engine);
+        let engine = Engine::open_with_config(&path, config(backend)).unwrap();
+        assert_eq!(engine.get_raw("k").unwrap(), Some(vec![1]));
+    }
+}
+
+#[test]
+fn lock_is_checked_before_the_encryption_key() {
+    let path = temp_db("key");
+    let key = EncryptionKey::Passphrase("secret".to_string());
+    let engine = Engine::open_with_config(&path, config(BackendKind::Log).encryption(key)).unwrap();
+
+    let wrong = Engine::open_with_config(&path, config(BackendKind::Log).encryption(EncryptionKey::Passphrase("guess".to_string())));
+    assert!(matches!(wrong, Err(DbError::DatabaseLocked)));
+    let missing = Engine::open_with_config(&path, config(BackendKind::Log));
+    assert!(matches!(missing, Err(DbError::DatabaseLocked)));
+    drop(engine);
+
+    let wrong = Engine::open_with_config(&path, config(BackendKind::Log).encryption(EncryptionKey::Passphrase("guess".to_string())));
+    assert!(matches!(wrong, Err(DbError::WrongEncryptionKey)));
+}
+
+#[test]
+fn read_only_open_coexists_with_a_writer() {
+    for backend in [BackendKind::Log, BackendKind::Lsm] {
+        let path = temp_db(&format!("reader-{:?}", backend));
+        let engine = Engine::open_with_config(&path, config(backend)).unwrap();
+        for i in 0..200 {
+            engine.set_raw(format!("k{:04}", i), vec![1; 32]).unwrap();
+        }
+
+        let reader = Engine::open_with_config(&path, config(backend).read_only(true)).unwrap();
+        assert_eq!(reader.scan_prefix("k").unwrap().len(), 200, "{:?}", backend);
+        assert_eq!(reader.get_raw("k0000").unwrap(), Some(vec![1; 32]));
+
+        assert!(matches!(reader.set_raw("k".to_string(), vec![1]), Err(DbError::ReadOnly)));
+        assert!(matches!(reader.delete_raw("k0000".to_string()), Err(DbError::ReadOnly)));
+        assert!(matches!(reader.compact(), Err(DbError::ReadOnly)));
+
+        // the writer carries on
+        engine.set_raw("k9999".to_string(), vec![2]).unwrap();
+        assert_eq!(engine.scan_prefix("k").unwrap().len(), 201);
+    }
+}
+
+#[test]
+fn read_only_open_survives_compaction_by_the_writer() {
+    for backend in [BackendKind::Log, BackendKind::Lsm] {
+        let path = temp_db(&format!("compacted-{:?}", backend));
+        let engine = Engine::open_with_config(&path, config(backend)).unwrap();
+        for round in 0..3u8 {
+            for i in 0..100 {
+                engine.set_raw(format!("k{:04}", i), vec![round; 32]).unwrap();
+            }
+        }
+
+        let reader = Engine::open_with_config(&path, config(backend).read_only(true)).unwrap();
+        // deletes every file the reader opened, except the active segment or log
+        engine.compact().unwrap();
+
+        assert_eq!(reader.get_raw("k0000").unwrap(), Some(vec![2; 32]), "{:?}", backend);
+        let rows = reader.scan_prefix("k").unwrap();
+        assert_eq!(rows.len(), 100, "{:?}", backend);
+        assert!(rows.iter().all(|(_, value)| *value == vec![2; 32]));
+    }
+}
+
+#[test]
+fn read_only_open_never_creates_a_database() {
+    let path = temp_db("missing");
+    let result = Engine::open_with_config(&path, EngineConfig::default().read_only(true));
+    assert!(matches!(result, Err(DbError::DatabaseNotFound)));
+    assert!(!path.exists());
+
+    // not even the encryption setup
+    let key = EncryptionKey::Passphrase("secret".to_string());
+    let result = Engine::open_with_config(&path, EngineConfig::default().read_only(true).encryption(key));
+    assert!(matches!(result, Err(DbError::DatabaseNotFound)));
+    assert!(!path.exists());
+}
+
+// -> Every file and directory under dir, files with their contents
+fn files(dir: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+    let mut found = Vec::new();
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            found.extend(files(&path));
+            found.push((path, Vec::new()));
+        } else {
+            found.push((path.clone(), std::fs::read(&path).unwrap()));
+        }
+    }
+    found.sort();
+    found
+}
+
+#[test]
+fn read_only_open_writes_nothing() {
+    for backend in [BackendKind::Log, BackendKind::Lsm] {
+        let root = temp_db(&format!("untouched-{:?}", backend));
+        let engine = Engine::open_with_config(root.join("shop"), config(backend)).unwrap();
+        for i in 0..200 {
+            engine.set_raw(format!("k{:04}", i), vec![1; 32]).unwrap();
+        }
+        drop(engine);
+
+        // a writer would rebuild these
+        std::fs::remove_dir_all(root.join("shop").join("schema")).ok();
+        if matches!(backend, BackendKind::Lsm) {
+            std::fs::remove_file(root.join("shop").join("lsm").join("sst-000001.bloom")).unwrap();
+        }
+        let before = files(&root);
+
+        drop(Enso::open_with_config(&root, "shop", config(backend).read_only(true)).unwrap());
+        assert!(files(&root) == before, "{:?}", backend);
+    }
+}
+
+#[test]
+fn enso_open_respects_the_lock() {
+    let root = temp_db("enso");
+    let mut db = Enso::open_at(&root, "shop").unwrap();
+    db.create_table("users", schema! {
+        id: Int => pk,
+        name: String,
+    }).unwrap();
+    db.insert_into("users", row![1, "amartya"]).unwrap();
+
+    // a second session shares the engine, a separate one is locked out
+    assert_eq!(Enso::open_at(&root, "shop").unwrap().select_all_from("users").unwrap().len(), 1);
+    assert!(matches!(Engine::open_with_config(root.join("shop"), EngineConfig::default()), Err(DbError::DatabaseLocked)));
+
+    let mut reader = Enso::open_with_config(&root, "shop", EngineConfig::default().read_only(true)).unwrap();
+    assert_eq!(reader.select_all_from("users").unwrap().len(), 1);
+    assert!(matches!(reader.query("INSERT INTO users VALUES (2, 'bea');"), Err(DbError::ReadOnly)));
+    assert!(matches!(reader.query("BEGIN;"), Err(DbError::ReadOnly)));
+}
@@ -0,0 +1,147 @@
+mod common;
+
+use enso::{BackendKind, DbError, Engine, EngineConfig};
+use common::temp_db;
+
+// small memtable so a few hundred writes spread over several tables
+fn config() -> EngineConfig {
+    common::config(BackendKind::Lsm).segment_size(8 * 1024)
+}
+
+#[test]
+fn lookups_and_scans_span_memtable_and_tables() {
+    let dir = temp_db("lookups");
+    let engine = Engine::open_with_config(&dir, config()).unwrap();
+
+    for i in 0..500 {
+        engine.set_raw(format!("users:{:04}", i), format!("user {}", i).into_bytes()).unwrap();
+        engine.set_raw(format!("posts:{:04}", i), vec![0; 32]).unwrap();
+    }
+    for i in (0..500).step_by(5) {
+        engine.delete_raw(format!("users:{:04}", i)).unwrap();
+    }
+
+    assert_eq!(engine.get_raw("users:0001").unwrap(), Some(b"user 1".to_vec()));
+    assert_eq!(engine.get_raw("users:0005").unwrap(), None);
+    assert_eq!(engine.get_raw("missing").unwrap(), None);
+
+    let users = engine.scan_prefix("users:").unwrap();
+    assert_eq!(users.len(), 400);
+    assert!(users.windows(2).all(|w| w[0].0 < w[1].0));
+    assert_eq!(engine.scan_prefix("posts:").unwrap().len(), 500);
+}
+
+#[test]
+fn unflushed_writes_survive_reopen() {
+    let dir = temp_db("reopen");
+    let engine = Engine::open_with_config(&dir, config()).unwrap();
+
+    for i in 0..300 {
+        engine.set_raw(format!("k{:04}", i), vec![1; 32]).unwrap();
+    }
+    engine.delete_raw("k0010".to_string()).unwrap();
+    engine.set_raw("k0020".to_string(), vec![2; 4]).unwrap();
+
+    drop(engine);
+    let engine = Engine::open_with_config(&dir, config()).unwrap();
+    assert!(engine.recovery_report().is_clean());
+    assert_eq!(engine.get_raw("k0010").unwrap(), None);
+    assert_eq!(engine.get_raw("k0020").unwrap(), Some(vec![2; 4]));
+    assert_eq!(engine.scan_prefix("k").unwrap().len(), 299);
+}
+
+#[test]
+fn corruption_inside_the_log_is_reported_not_truncated() {
+    let dir = temp_db("corrupt-wal");
+    let engine = Engine::open_with_config(&dir, config()).unwrap();
+    for i in 0..20 {
+        engine.set_raw(format!("k{:04}", i), vec![1; 32]).unwrap();
+    }
+    drop(engine);
+
+    // flip a byte of the second record, the ones after it are still intact
+    let wal = dir.join("lsm").join("wal.log");
+    let mut bytes = std::fs::read(&wal).unwrap();
+    bytes[100] ^= 0xff;
+    std::fs::write(&wal, &bytes).unwrap();
+
+    match Engine::open_with_config(&dir, config()) {
+        Err(DbError::Corruption { segment, .. }) => assert_eq!(segment, "wal.log"),
+        other => panic!("expected corruption, got {:?}", other.err()),
+    }
+    assert_eq!(std::fs::read(&wal).unwrap(), bytes);
+}
+
+#[test]
+fn compaction_merges_tables_and_drops_deleted_rows() {
+    let dir = temp_db("compaction");
+    let engine = Engine::open_with_config(&dir, config()).unwrap();
+
+    for round in 0..3u8 {
+        for i in 0..200 {
+            engine.set_raw(format!("k{:04}", i), vec![round; 32]).unwrap();
+        }
+    }
+    for i in 0..100 {
+        engine.delete_raw(format!("k{:04}", i)).unwrap();
+    }
+
+    engine.compact().unwrap();
+    assert_eq!(engine.compaction_stats().runs, 1);
+    assert_eq!(engine.get_raw("k0150").unwrap(), Some(vec![2; 32]));
+    assert_eq!(engine.scan_prefix("k").unwrap().len(), 100);
+
+    drop(engine);
+    let engine = Engine::open_with_config(&dir, config()).unwrap();
+    assert_eq!(engine.get_raw("k0050").unwrap(), None);
+    assert_eq!(engine.get_raw("k0150").unwrap(), Some(vec![2; 32]));
+    assert_eq!(engine.scan_prefix("k").unwrap().len(), 100);
+}
+
+#[test]
+fn missing_bloom_filters_are_rebuilt() {
+    let dir = temp_db("bloom");
+    let engine = Engine::open_with_config(&dir, config()).unwrap();
+
+    for i in 0..300 {
+        engine.set_raw(format!("k{:04}", i), vec![1; 32]).unwrap();
+    }
+    drop(engine);
+
+    let filters: Vec<_> = std::fs::read_dir(dir.join("lsm"))
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "bloom"))
+        .collect();
+    assert!(!filters.is_empty());
+    for filter in &filters {
+        std::fs::remove_file(filter).unwrap();
+    }
+
+    let engine = Engine::open_with_config(&dir, config()).unwrap();
+    assert_eq!(engine.recovery_report().rebuilt_indexes.len(), filters.len());
+    assert!(filters.iter().all(|f| f.exists()));
+    for i in 0..300 {
+        assert_eq!(engine.get_raw(&format!("k{:04}", i)).unwrap(), Some(vec![1; 32]));
+    }
+    assert_eq!(engine.get_raw("k9999").unwrap(), None);
+}
+
+#[test]
+fn backend_is_kept_from_creation() {
+    let path = temp_db("kept");
+    let engine = Engine::open_with_config(&path, config()).unwrap();
+    engine.set_raw("k".to_string(), vec![1]).unwrap();
+    drop(engine);
+
+    // a plain open picks the backend up from the database
+    let engine = Engine::open(&path).unwrap();
+    assert_eq!(engine.config().backend, BackendKind::Lsm);
+    assert_eq!(engine.get_raw("k").unwrap(), Some(vec![1]));
+    drop(engine);
+
+    // asking for another one fails instead of starting an empty store next to it
+    let result = Engine::open_with_config(&path, common::config(BackendKind::Log));
+    assert!(matches!(result, Err(DbError::ConfigMismatch { .. })));
+    assert!(!path.join("manifest.json").exists());
+}
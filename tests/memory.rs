@@ -0,0 +1,34 @@
+use enso::{row, schema, Enso, QueryResult, Value};
+
+#[test]
+fn in_memory_database_supports_tables_and_queries() {
+    let mut db = Enso::in_memory("test_db").unwrap();
+
+    db.create_table("users", schema! {
+        id: Int => pk,
+        name: String,
+    }).unwrap();
+
+    db.insert(row![1, "amartya"]).unwrap();
+    db.insert(row![2, "bea"]).unwrap();
+    db.delete_by_pk(2).unwrap();
+
+    let user = db.select_by_pk(1).unwrap().unwrap();
+    assert!(matches!(&user[1], Value::String(name) if name == "amartya"));
+    assert!(db.select_by_pk(2).unwrap().is_none());
+    assert_eq!(db.select_all().unwrap().len(), 1);
+
+    assert!(matches!(db.query("SELECT * FROM users;").unwrap(), QueryResult::Rows { rows: Some(rows), .. } if rows.len() == 1));
+    assert!(db.use_table("missing").is_err());
+}
+
+#[test]
+fn in_memory_databases_are_independent() {
+    let mut a = Enso::in_memory("test_db").unwrap();
+    let mut b = Enso::in_memory("test_db").unwrap();
+
+    a.create_table("users", schema! { id: Int => pk }).unwrap();
+    a.insert(row![1]).unwrap();
+
+    assert!(b.use_table("users").is_err());
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use std::path::Path;
+
+use enso::{row, DbError, EngineConfig, Enso, Value};
+use common::temp_db;
+
+// -> Record of the original format: [key_len][val_len][timestamp][deleted][key][value]
+fn original(key: &str, value: &[u8], deleted: bool) -> Vec<u8> {
+    let mut record = (key.len() as u32).to_be_bytes().to_vec();
+    record.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    record.extend_from_slice(&7u64.to_be_bytes());
+    record.push(deleted as u8);
+    record.extend_from_slice(key.as_bytes());
+    record.extend_from_slice(value);
+    record
+}
+
+// -> Row [id, name] as the row codec stores it
+fn user(id: i64, name: &str) -> Vec<u8> {
+    let mut row = vec![1];
+    row.extend_from_slice(&id.to_be_bytes());
+    row.push(1);
+    row.extend_from_slice(&(name.len() as u32).to_be_bytes());
+    row.extend_from_slice(name.as_bytes());
+    row
+}
+
+// -> Store shared by databases shop and blog as older versions left it under base
+fn shared_store(base: &Path) {
+    std::fs::create_dir_all(base.join("segments")).unwrap();
+    std::fs::write(
+        base.join("manifest.json"),
+        r#"{ "active_segment": "enso-0001.log", "segments": ["enso-0001.log"], "last_compaction": null }"#,
+    ).unwrap();
+
+    let mut log = original("shop:users:i:1", &user(1, "amartya"), false);
+    log.extend(original("blog:users:i:1", &user(1, "someone else"), false));
+    log.extend(original("shop:users:i:2", &user(2, "bea"), false));
+    log.extend(original("shop:users:i:2", &[], true));
+    log.extend(original("shop:users:i:3", &user(3, "cy"), false));
+    std::fs::write(base.join("segments").join("enso-0001.log"), log).unwrap();
+
+    for db in ["shop", "blog"] {
+        let dir = base.join("schema").join(db);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("users.json"),
+            r#"{ "name": "users", "columns": [{ "name": "id", "dtype": "Int" }, { "name": "name", "dtype": "String" }], "primary_key": 0 }"#,
+        ).unwrap();
+    }
+}
+
+#[test]
+fn database_in_the_shared_store_moves_into_its_own_directory() {
+    let root = temp_db("shared-store");
+    shared_store(&root);
+
+    let mut db = Enso::open_at(&root, "shop").unwrap();
+    let rows = db.select_all_from("users").unwrap();
+    assert_eq!(rows.len(), 2);
+    let amartya = db.select_by_pk_from("users", 1).unwrap().unwrap();
+    assert!(matches!(&amartya[1], Value::String(name) if name == "amartya"));
+    assert!(db.select_by_pk_from("users", 2).unwrap().is_none());
+    db.insert_into("users", row![4, "dee"]).unwrap();
+    drop(db);
+
+    // moved once, the shared store is left as it was for the other databases
+    assert!(root.join("shop").join("schema").join("users.json").exists());
+    assert!(!root.join("schema").join("shop").exists());
+    assert!(root.join("schema").join("blog").exists());
+
+    let mut db = Enso::open_at(&root, "shop").unwrap();
+    assert_eq!(db.select_all_from("users").unwrap().len(), 3);
+    drop(db);
+
+    let mut blog = Enso::open_at(&root, "blog").unwrap();
+    let rows = blog.select_all_from("users").unwrap();
+    assert_eq!(rows.len(), 1);
+    assert!(matches!(&rows[0][1], Value::String(name) if name == "someone else"));
+}
+
+#[test]
+fn read_only_open_of_a_database_in_the_shared_store_is_refused() {
+    let root = temp_db("shared-read-only");
+    shared_store(&root);
+
+    let result = Enso::open_with_config(&root, "shop", EngineConfig::default().read_only(true));
+    assert!(matches!(result, Err(DbError::NotMigrated { db }) if db == "shop"));
+    assert!(root.join("schema").join("shop").exists());
+}
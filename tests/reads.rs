@@ -0,0 +1,93 @@
+mod common;
+
+use std::sync::Arc;
+
+use enso::{BackendKind, Engine, EngineConfig};
+use common::temp_db;
+
+// small segments and fewer open handles than segments, so lookups keep evicting each other's handles
+fn config() -> EngineConfig {
+    backend_config(BackendKind::Log)
+}
+
+fn backend_config(backend: BackendKind) -> EngineConfig {
+    common::config(backend).segment_size(2 * 1024).max_open_files(2)
+}
+
+#[test]
+fn concurrent_lookups_share_a_small_handle_cache() {
+    concurrent_lookups(BackendKind::Log);
+}
+
+#[test]
+fn concurrent_lsm_lookups_share_a_small_handle_cache() {
+    concurrent_lookups(BackendKind::Lsm);
+}
+
+fn concurrent_lookups(backend: BackendKind) {
+    let path = temp_db("concurrent");
+    let engine = Arc::new(Engine::open_with_config(&path, backend_config(backend)).unwrap());
+    for i in 0..400 {
+        engine.set_raw(format!("k{:04}", i), (i as u64).to_be_bytes().repeat(4)).unwrap();
+    }
+    assert!(engine.storage.segment_count() > 10);
+
+    let readers: Vec<_> = (0..4)
+        .map(|t| {
+            let engine = Arc::clone(&engine);
+            std::thread::spawn(move || {
+                for round in 0..5 {
+                    for i in (t * 7 + round..400).step_by(3) {
+                        let value = engine.get_raw(&format!("k{:04}", i)).unwrap();
+                        assert_eq!(value, Some((i as u64).to_be_bytes().repeat(4)));
+                    }
+                }
+            })
+        })
+        .collect();
+
+    // compaction deletes segments (or tables) whose handles may be cached
+    engine.compact().unwrap();
+    for reader in readers {
+        reader.join().unwrap();
+    }
+
+    assert_eq!(engine.scan_prefix("k").unwrap().len(), 400);
+    for i in 0..400 {
+        assert_eq!(engine.get_raw(&format!("k{:04}", i)).unwrap(), Some((i as u64).to_be_bytes().repeat(4)));
+    }
+}
+
+#[test]
+fn lookups_after_compaction_read_the_merged_segment() {
+    let path = temp_db("compacted");
+    let engine = Engine::open_with_config(&path, config()).unwrap();
+    for round in 0..3u8 {
+        for i in 0..100 {
+            engine.set_raw(format!("k{:03}", i), vec![round; 32]).unwrap();
+        }
+        // every segment gets a cached handle before compaction replaces it
+        assert_eq!(engine.get_raw("k000").unwrap(), Some(vec![round; 32]));
+        assert_eq!(engine.scan_prefix("k").unwrap().len(), 100);
+    }
+
+    engine.compact().unwrap();
+    assert!(engine.scan_prefix("k").unwrap().iter().all(|(_, value)| *value == vec![2; 32]));
+    drop(engine);
+
+    let engine = Engine::open_with_config(&path, config()).unwrap();
+    assert_eq!(engine.get_raw("k099").unwrap(), Some(vec![2; 32]));
+}
+
+#[test]
+fn lookups_see_records_appended_after_the_segment_was_opened() {
+    let path = temp_db("appended");
+    let engine = Engine::open_with_config(&path, config()).unwrap();
+    // every lookup reads the active segment through the handle the first one opened, or the
+    // handle of a segment sealed since
+    for i in 0..200 {
+        engine.set_raw(format!("k{:04}", i), vec![i as u8; 16]).unwrap();
+        assert_eq!(engine.get_raw(&format!("k{:04}", i)).unwrap(), Some(vec![i as u8; 16]));
+    }
+    assert!(engine.storage.segment_count() > 1);
+}
@@ -0,0 +1,140 @@
+mod common;
+
+use enso::{BackendKind, DbError, Engine, EngineConfig};
+use common::{config, temp_db};
+
+// -> The file records are appended to: the active segment, or the write-ahead log
+fn log_of(path: &std::path::Path, backend: BackendKind) -> std::path::PathBuf {
+    match backend {
+        BackendKind::Log => path.join("segments").join("enso-0001.log"),
+        BackendKind::Lsm => path.join("lsm").join("wal.log"),
+    }
+}
+
+// -> Write records a, b and c, returns where b starts and ends in the log
+fn write_three(path: &std::path::Path, backend: BackendKind) -> (u64, u64) {
+    let engine = Engine::open_with_config(path, config(backend)).unwrap();
+    let len = || std::fs::metadata(log_of(path, backend)).unwrap().len();
+    engine.set_raw("a".to_string(), vec![1; 64]).unwrap();
+    let start = len();
+    engine.set_raw("b".to_string(), vec![2; 64]).unwrap();
+    let end = len();
+    engine.set_raw("c".to_string(), vec![3; 64]).unwrap();
+    (start, end)
+}
+
+#[test]
+fn checksum_mismatch_is_reported_with_segment_and_offset() {
+    for backend in [BackendKind::Log, BackendKind::Lsm] {
+        let path = temp_db(&format!("crc-{:?}", backend));
+        let (start, end) = write_three(&path, backend);
+
+        // flip a byte inside the value of b, a and c are intact
+        let log = log_of(&path, backend);
+        let mut data = std::fs::read(&log).unwrap();
+        data[((start + end) / 2) as usize] ^= 0xff;
+        std::fs::write(&log, data).unwrap();
+
+        let segment = log.file_name().unwrap().to_string_lossy().into_owned();
+        match Engine::open_with_config(&path, config(backend)) {
+            Err(DbError::Corruption { segment: s, offset }) => assert_eq!((s, offset), (segment, start), "{:?}", backend),
+            other => panic!("{:?}: expected corruption, got {:?}", backend, other.err()),
+        }
+    }
+}
+
+#[test]
+fn complete_final_record_failing_its_checksum_is_corruption() {
+    for backend in [BackendKind::Log, BackendKind::Lsm] {
+        let path = temp_db(&format!("crc-tail-{:?}", backend));
+        let (_, end) = write_three(&path, backend);
+
+        // c is all there, so this isn't a write cut short
+        let log = log_of(&path, backend);
+        let mut data = std::fs::read(&log).unwrap();
+        let last = data.len() - 10;
+        data[last] ^= 0xff;
+        std::fs::write(&log, data).unwrap();
+
+        match Engine::open_with_config(&path, config(backend)) {
+            Err(DbError::Corruption { offset, .. }) => assert_eq!(offset, end, "{:?}", backend),
+            other => panic!("{:?}: expected corruption, got {:?}", backend, other.err()),
+        }
+    }
+}
+
+#[test]
+fn record_cut_short_at_the_tail_is_truncated() {
+    for backend in [BackendKind::Log, BackendKind::Lsm] {
+        let path = temp_db(&format!("torn-{:?}", backend));
+        let (_, end) = write_three(&path, backend);
+
+        // the crash hit while c was being written
+        let log = log_of(&path, backend);
+        let len = std::fs::metadata(&log).unwrap().len();
+        std::fs::OpenOptions::new().write(true).open(&log).unwrap().set_len(len - 5).unwrap();
+
+        let engine = Engine::open_with_config(&path, config(backend)).unwrap();
+        assert_eq!(engine.recovery_report().truncated_bytes, len - 5 - end, "{:?}", backend);
+        assert_eq!(engine.get_raw("b").unwrap(), Some(vec![2; 64]));
+        assert_eq!(engine.get_raw("c").unwrap(), None);
+        drop(engine);
+
+        assert_eq!(std::fs::metadata(&log).unwrap().len(), end, "{:?}", backend);
+        let engine = Engine::open_with_config(&path, config(backend)).unwrap();
+        assert!(engine.recovery_report().is_clean(), "{:?}", backend);
+    }
+}
+
+#[test]
+fn missing_and_invalid_hints_are_regenerated() {
+    let path = temp_db("hints");
+    let engine = Engine::open_with_config(&path, config(BackendKind::Log)).unwrap();
+    // enough to seal the first two segments
+    for i in 0..400 {
+        engine.set_raw(format!("k{:04}", i), vec![1; 32]).unwrap();
+    }
+    drop(engine);
+
+    let index = path.join("index");
+    let first = std::fs::read(index.join("enso-0001.hint")).unwrap();
+    std::fs::remove_file(index.join("enso-0001.hint")).unwrap();
+    std::fs::write(index.join("enso-0002.hint"), b"not a hint").unwrap();
+
+    let engine = Engine::open_with_config(&path, config(BackendKind::Log)).unwrap();
+    let report = engine.recovery_report();
+    assert_eq!(report.rebuilt_indexes, vec!["enso-0001.log".to_string(), "enso-0002.log".to_string()]);
+    assert!(report.restored_entries > 0);
+    assert_eq!(engine.scan_prefix("k").unwrap().len(), 400);
+    drop(engine);
+
+    // written back, the next open reads them again
+    assert_eq!(std::fs::read(index.join("enso-0001.hint")).unwrap(), first);
+    let engine = Engine::open_with_config(&path, config(BackendKind::Log)).unwrap();
+    assert!(engine.recovery_report().is_clean());
+    assert_eq!(engine.get_raw("k0000").unwrap(), Some(vec![1; 32]));
+}
+
+#[test]
+fn config_file_in_the_database_directory_is_loaded() {
+    let path = temp_db("config");
+    std::fs::create_dir_all(&path).unwrap();
+    std::fs::write(
+        path.join(EngineConfig::FILE_NAME),
+        r#"{ "backend": "Lsm", "segment_size": 2048, "max_segments": 3, "max_open_files": 5 }"#,
+    ).unwrap();
+
+    let engine = Engine::open(&path).unwrap();
+    assert_eq!(engine.config().backend, BackendKind::Lsm);
+    assert_eq!(engine.config().segment_size, 2048);
+    assert_eq!(engine.config().max_segments, 3);
+    assert_eq!(engine.config().max_open_files, 5);
+    // settings it leaves out keep their defaults
+    assert_eq!(engine.config().durability, EngineConfig::default().durability);
+    engine.set_raw("k".to_string(), vec![1]).unwrap();
+    drop(engine);
+
+    assert!(path.join("lsm").join("manifest.json").exists());
+    std::fs::write(path.join(EngineConfig::FILE_NAME), b"{ not json").unwrap();
+    assert!(Engine::open(&path).is_err());
+}
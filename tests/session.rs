@@ -0,0 +1,110 @@
+mod common;
+
+use enso::{row, schema, BackendKind, CompactionPolicy, DbError, EncryptionKey, Engine, EngineConfig, Enso};
+use common::temp_db;
+
+fn users(db: &mut Enso) {
+    db.create_table("users", schema! {
+        id: Int => pk,
+        name: String,
+    }).unwrap();
+}
+
+#[test]
+fn sessions_on_one_database_share_its_engine() {
+    let root = temp_db("shared");
+    let mut first = Enso::open_at(&root, "shop").unwrap();
+    let mut second = Enso::open_at(&root, "shop").unwrap();
+
+    // a table created in one session is there for the other
+    users(&mut first);
+    second.insert_into("users", row![1, "amartya"]).unwrap();
+    assert_eq!(first.select_all_from("users").unwrap().len(), 1);
+
+    let mut third = second.clone();
+    third.query("INSERT INTO users VALUES (2, 'bea');").unwrap();
+    assert_eq!(first.select_all_from("users").unwrap().len(), 2);
+
+    // the database closes with its last session
+    drop(first);
+    drop(second);
+    assert!(matches!(Engine::open_with_config(root.join("shop"), EngineConfig::default()), Err(DbError::DatabaseLocked)));
+    drop(third);
+    let engine = Engine::open_with_config(root.join("shop"), EngineConfig::default()).unwrap();
+    assert_eq!(engine.scan_prefix("users:").unwrap().len(), 2);
+}
+
+#[test]
+fn later_sessions_must_agree_with_the_open_config() {
+    let root = temp_db("config");
+    let key = EncryptionKey::Passphrase("secret".to_string());
+    let config = EngineConfig::default().encryption(key);
+    let _first = Enso::open_with_config(&root, "shop", config.clone()).unwrap();
+
+    assert!(matches!(Enso::open_with_config(&root, "shop", config.clone().backend(BackendKind::Lsm)), Err(DbError::ConfigMismatch { .. })));
+    assert!(matches!(Enso::open_with_config(&root, "shop", config.clone().archive(true)), Err(DbError::ConfigMismatch { .. })));
+    assert!(matches!(Enso::open_at(&root, "shop"), Err(DbError::EncryptionKeyRequired)));
+    let wrong = EngineConfig::default().encryption(EncryptionKey::Passphrase("guess".to_string()));
+    assert!(matches!(Enso::open_with_config(&root, "shop", wrong), Err(DbError::WrongEncryptionKey)));
+
+    // settings that don't change how the database is stored are the first session's
+    Enso::open_with_config(&root, "shop", config.max_segments(3)).unwrap();
+}
+
+#[test]
+fn database_can_be_reopened_while_its_last_session_is_dropped() {
+    let root = temp_db("reopen");
+    // the last session's drop waits for the background compaction the writes start
+    let config = common::config(BackendKind::Log).compaction(CompactionPolicy::Automatic).max_segments(2);
+    let mut db = Enso::open_with_config(&root, "shop", config.clone()).unwrap();
+    users(&mut db);
+
+    for round in 0..10 {
+        for i in 0..200 {
+            db.insert_into("users", row![round * 200 + i, "x".repeat(64)]).unwrap();
+        }
+        let closing = std::thread::spawn(move || drop(db));
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        db = Enso::open_with_config(&root, "shop", config.clone()).unwrap();
+        closing.join().unwrap();
+    }
+    assert_eq!(db.select_all_from("users").unwrap().len(), 2000);
+}
+
+#[test]
+fn refused_open_racing_the_last_session_drop_does_not_hang() {
+    let root = temp_db("refused");
+    let config = |secret: &str| EngineConfig::default().encryption(EncryptionKey::Passphrase(secret.to_string()));
+    let (done, finished) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for _ in 0..5 {
+            let db = Enso::open_with_config(&root, "shop", config("secret")).unwrap();
+            let closing = std::thread::spawn(move || drop(db));
+            // the first session goes while the wrong key is checked, the refusal drops the last reference
+            let _ = Enso::open_with_config(&root, "shop", config("guess"));
+            closing.join().unwrap();
+        }
+        done.send(()).unwrap();
+    });
+    finished.recv_timeout(std::time::Duration::from_secs(60)).expect("opening hung");
+}
+
+#[test]
+fn each_session_has_its_own_transaction() {
+    let mut db = Enso::in_memory("test_db").unwrap();
+    users(&mut db);
+    let mut other = db.clone();
+
+    db.query("BEGIN;").unwrap();
+    db.query("INSERT INTO users VALUES (1, 'amartya');").unwrap();
+    assert!(!other.in_transaction());
+    assert!(matches!(other.query("COMMIT;"), Err(DbError::NoTransaction)));
+    assert!(other.select_all_from("users").unwrap().is_empty());
+
+    // a clone starts outside the transaction of the session it came from
+    assert!(!db.clone().in_transaction());
+
+    other.insert_into("users", row![2, "bea"]).unwrap();
+    db.query("COMMIT;").unwrap();
+    assert_eq!(other.select_all_from("users").unwrap().len(), 2);
+}
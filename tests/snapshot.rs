@@ -0,0 +1,111 @@
+mod common;
+
+use std::sync::Arc;
+
+use enso::{BackendKind, Engine, EngineConfig};
+use common::{temp_db, config, TempDir};
+
+// -> One engine per backend, the on-disk ones in subdirectories of root
+fn engines(root: &TempDir) -> Vec<Engine> {
+    [BackendKind::Log, BackendKind::Lsm]
+        .into_iter()
+        .map(|backend| Engine::open_with_config(root.join(format!("{:?}", backend)), config(backend)).unwrap())
+        .chain([Engine::in_memory(EngineConfig::default())])
+        .collect()
+}
+
+#[test]
+fn snapshot_ignores_later_writes_and_compaction() {
+    let root = temp_db("view");
+    for engine in engines(&root) {
+        for i in 0..200 {
+            engine.set_raw(format!("k{:04}", i), vec![1; 32]).unwrap();
+        }
+        let snapshot = engine.snapshot();
+
+        for i in 0..200 {
+            if i % 2 == 0 {
+                engine.delete_raw(format!("k{:04}", i)).unwrap();
+            } else {
+                engine.set_raw(format!("k{:04}", i), vec![2; 32]).unwrap();
+            }
+        }
+        engine.set_raw("k9999".to_string(), vec![3; 32]).unwrap();
+        // old versions are compacted out of the live segments, the snapshot still reads them
+        engine.compact().unwrap();
+
+        let records = snapshot.scan_prefix("k").unwrap();
+        assert_eq!(records.len(), 200);
+        assert!(records.iter().all(|(_, value)| *value == vec![1; 32]));
+        assert_eq!(snapshot.get_raw("k0000").unwrap(), Some(vec![1; 32]));
+        assert_eq!(snapshot.get_raw("k9999").unwrap(), None);
+
+        assert_eq!(engine.scan_prefix("k").unwrap().len(), 101);
+        assert_eq!(engine.get_raw("k0000").unwrap(), None);
+        assert_eq!(engine.get_raw("k0001").unwrap(), Some(vec![2; 32]));
+
+        // a later snapshot sees the writes the earlier one doesn't
+        let later = engine.snapshot();
+        engine.set_raw("k0001".to_string(), vec![4; 32]).unwrap();
+        assert!(later.seq() > snapshot.seq());
+        assert_eq!(later.get_raw("k0001").unwrap(), Some(vec![2; 32]));
+        assert_eq!(snapshot.get_raw("k0001").unwrap(), Some(vec![1; 32]));
+        drop(snapshot);
+        assert_eq!(later.get_raw("k0001").unwrap(), Some(vec![2; 32]));
+        drop(later);
+        assert_eq!(engine.get_raw("k0001").unwrap(), Some(vec![4; 32]));
+    }
+}
+
+#[test]
+fn scans_see_a_consistent_state_while_writers_run() {
+    let root = temp_db("concurrent");
+    for engine in engines(&root) {
+        let engine = Arc::new(engine);
+        for i in 0..100 {
+            engine.set_raw(format!("k{:03}", i), 0u64.to_be_bytes().to_vec()).unwrap();
+        }
+
+        // every round rewrites all keys with the round number, in order
+        let writer = {
+            let engine = Arc::clone(&engine);
+            std::thread::spawn(move || {
+                for round in 1..30u64 {
+                    for i in 0..100 {
+                        engine.set_raw(format!("k{:03}", i), round.to_be_bytes().to_vec()).unwrap();
+                    }
+                }
+            })
+        };
+
+        while !writer.is_finished() {
+            let rounds: Vec<u64> = engine.scan_prefix("k").unwrap()
+                .into_iter()
+                .map(|(_, value)| u64::from_be_bytes(value.try_into().unwrap()))
+                .collect();
+            assert_eq!(rounds.len(), 100);
+            // keys written later in a round can only be behind the earlier ones, by at most one round
+            assert!(rounds.windows(2).all(|w| w[0] == w[1] || w[0] == w[1] + 1), "{:?}", rounds);
+        }
+        writer.join().unwrap();
+    }
+}
+
+#[test]
+fn write_sequence_survives_a_reopen() {
+    for backend in [BackendKind::Log, BackendKind::Lsm] {
+        let path = temp_db(&format!("reopen-{:?}", backend));
+        let engine = Engine::open_with_config(&path, config(backend)).unwrap();
+        for i in 0..300 {
+            engine.set_raw(format!("k{:04}", i), vec![1; 32]).unwrap();
+        }
+        let seq = engine.snapshot().seq();
+        assert_eq!(seq, 300);
+        drop(engine);
+
+        let engine = Engine::open_with_config(&path, config(backend)).unwrap();
+        assert_eq!(engine.snapshot().seq(), seq, "{:?}", backend);
+        engine.set_raw("k".to_string(), vec![1]).unwrap();
+        assert_eq!(engine.snapshot().seq(), seq + 1);
+    }
+}
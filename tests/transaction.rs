@@ -0,0 +1,161 @@
+mod common;
+
+use std::fs::OpenOptions;
+
+use enso::{row, schema, BackendKind, DbError, EngineConfig, Enso, QueryResult, Value};
+use common::temp_db;
+
+fn users(db: &mut Enso) {
+    db.create_table("users", schema! {
+        id: Int => pk,
+        name: String,
+    }).unwrap();
+}
+
+#[test]
+fn commit_applies_and_rollback_drops_buffered_writes() {
+    let mut db = Enso::in_memory("test_db").unwrap();
+    users(&mut db);
+    db.insert_into("users", row![1, "amartya"]).unwrap();
+
+    db.query("BEGIN;").unwrap();
+    db.query("INSERT INTO users VALUES (2, 'bea');").unwrap();
+    db.query("DELETE FROM users WHERE id = 1;").unwrap();
+    // the transaction sees its own writes, other sessions only once it's committed
+    let rows = db.select_all_from("users").unwrap();
+    assert_eq!(rows.len(), 1);
+    assert!(matches!(rows[0][0], Value::Int(2)));
+    assert!(matches!(db.clone().select_all_from("users").unwrap()[0][0], Value::Int(1)));
+    assert!(matches!(db.query("BEGIN;"), Err(DbError::TransactionInProgress)));
+    assert!(matches!(db.query("COMMIT;").unwrap(), QueryResult::Affected(2)));
+
+    let rows = db.select_all_from("users").unwrap();
+    assert_eq!(rows.len(), 1);
+    assert!(matches!(rows[0][0], Value::Int(2)));
+
+    db.query("BEGIN;").unwrap();
+    db.query("DELETE FROM users WHERE id = 2;").unwrap();
+    db.query("ROLLBACK;").unwrap();
+    assert_eq!(db.select_all_from("users").unwrap().len(), 1);
+
+    assert!(matches!(db.query("COMMIT;"), Err(DbError::NoTransaction)));
+    assert!(matches!(db.query("ROLLBACK;"), Err(DbError::NoTransaction)));
+}
+
+#[test]
+fn transaction_reads_its_own_writes_over_a_snapshot() {
+    let mut db = Enso::in_memory("test_db").unwrap();
+    users(&mut db);
+    db.insert_into("users", row![1, "amartya"]).unwrap();
+    let mut other = db.clone();
+
+    db.query("BEGIN;").unwrap();
+    db.query("INSERT INTO users VALUES (2, 'bea');").unwrap();
+    assert!(matches!(db.query("SELECT * FROM users WHERE id = 2;").unwrap(), QueryResult::Rows { rows: Some(rows), .. } if rows.len() == 1));
+
+    // writes committed by others after BEGIN aren't seen
+    other.insert_into("users", row![3, "chen"]).unwrap();
+    other.delete_by_pk_from("users", 1).unwrap();
+    assert_eq!(db.select_all_from("users").unwrap().len(), 2);
+    assert!(db.select_by_pk_from("users", 1).unwrap().is_some());
+    assert!(db.select_by_pk_from("users", 3).unwrap().is_none());
+
+    db.query("COMMIT;").unwrap();
+    assert_eq!(db.select_all_from("users").unwrap().len(), 2);
+}
+
+#[test]
+fn commit_fails_if_a_written_row_changed_since_begin() {
+    let root = temp_db("conflict");
+    let mut db = Enso::open_at(&root, "shop").unwrap();
+    users(&mut db);
+    db.insert_into("users", row![1, "amartya"]).unwrap();
+    let mut other = db.clone();
+
+    db.query("BEGIN;").unwrap();
+    db.query("INSERT INTO users VALUES (1, 'mine');").unwrap();
+    db.query("INSERT INTO users VALUES (2, 'bea');").unwrap();
+    other.insert_into("users", row![1, "theirs"]).unwrap();
+
+    // nothing of the transaction is written, and it's over
+    assert!(matches!(db.query("COMMIT;"), Err(DbError::TransactionConflict)));
+    assert!(!db.in_transaction());
+    assert!(db.select_by_pk_from("users", 2).unwrap().is_none());
+    assert!(matches!(&db.select_by_pk_from("users", 1).unwrap().unwrap()[1], Value::String(name) if name == "theirs"));
+
+    // rows nobody else touched commit fine
+    db.query("BEGIN;").unwrap();
+    db.query("INSERT INTO users VALUES (2, 'bea');").unwrap();
+    other.insert_into("users", row![3, "chen"]).unwrap();
+    assert!(matches!(db.query("COMMIT;").unwrap(), QueryResult::Affected(1)));
+}
+
+#[test]
+fn delete_reports_the_rows_it_removed() {
+    let mut db = Enso::in_memory("test_db").unwrap();
+    users(&mut db);
+    db.insert_into("users", row![1, "amartya"]).unwrap();
+
+    assert!(matches!(db.query("DELETE FROM users WHERE id = 2;").unwrap(), QueryResult::Affected(0)));
+    assert!(matches!(db.query("DELETE FROM users WHERE id = 1;").unwrap(), QueryResult::Affected(1)));
+    assert!(matches!(db.query("DELETE FROM users WHERE id = 1;").unwrap(), QueryResult::Affected(0)));
+}
+
+#[test]
+fn transaction_handle_rolls_back_when_dropped() {
+    let mut db = Enso::in_memory("test_db").unwrap();
+    users(&mut db);
+
+    {
+        let mut tx = db.transaction().unwrap();
+        tx.insert_into("users", row![1, "amartya"]).unwrap();
+        tx.insert_into("users", row![2, "bea"]).unwrap();
+    }
+    assert!(!db.in_transaction());
+    assert!(db.select_all_from("users").unwrap().is_empty());
+
+    let mut tx = db.transaction().unwrap();
+    tx.insert_into("users", row![1, "amartya"]).unwrap();
+    tx.insert_into("users", row![2, "bea"]).unwrap();
+    assert_eq!(tx.commit().unwrap(), 2);
+    assert_eq!(db.select_all_from("users").unwrap().len(), 2);
+}
+
+#[test]
+fn recovery_drops_a_batch_cut_short() {
+    for backend in [BackendKind::Log, BackendKind::Lsm] {
+        let root = temp_db(&format!("{:?}", backend));
+        let config = EngineConfig::default().backend(backend).segment_size(1024 * 1024);
+        let mut db = Enso::open_with_config(&root, "shop", config.clone()).unwrap();
+        users(&mut db);
+
+        let mut tx = db.transaction().unwrap();
+        for i in 0..50 {
+            tx.insert_into("users", row![i, "first"]).unwrap();
+        }
+        tx.commit().unwrap();
+
+        let mut tx = db.transaction().unwrap();
+        for i in 50..100 {
+            tx.insert_into("users", row![i, "second"]).unwrap();
+        }
+        tx.commit().unwrap();
+        drop(db);
+
+        // crash in the middle of writing the second batch
+        let log = match backend {
+            BackendKind::Log => root.join("shop").join("segments").join("enso-0001.log"),
+            BackendKind::Lsm => root.join("shop").join("lsm").join("wal.log"),
+        };
+        let file = OpenOptions::new().write(true).open(&log).unwrap();
+        let len = file.metadata().unwrap().len();
+        file.set_len(len - 100).unwrap();
+        drop(file);
+
+        let mut db = Enso::open_with_config(&root, "shop", config).unwrap();
+        assert!(db.recovery_report().unwrap().truncated_bytes > 0);
+        let rows = db.select_all_from("users").unwrap();
+        assert_eq!(rows.len(), 50, "{:?}", backend);
+        assert!(rows.iter().all(|row| matches!(&row[1], Value::String(name) if name == "first")));
+    }
+}
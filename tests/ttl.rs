@@ -0,0 +1,87 @@
+mod common;
+
+use std::time::Duration;
+
+use enso::{row, BackendKind, Engine, EngineConfig, Enso};
+use common::{temp_db, config};
+
+// a 2 second ttl is still running right after the write and has surely run out after this
+const EXPIRED: Duration = Duration::from_millis(2100);
+
+#[test]
+fn expired_keys_are_hidden_and_compacted_away() {
+    let root = temp_db("expired");
+    let engines: Vec<_> = [BackendKind::Log, BackendKind::Lsm]
+        .into_iter()
+        .map(|backend| Engine::open_with_config(root.join(format!("{:?}", backend)), config(backend)).unwrap())
+        .chain([Engine::in_memory(EngineConfig::default())])
+        .collect();
+
+    for engine in &engines {
+        for i in 0..20 {
+            engine.set_raw_with_ttl(format!("session:{:02}", i), vec![1; 32], Duration::from_secs(2)).unwrap();
+        }
+        engine.set_raw("user:1".to_string(), vec![2; 32]).unwrap();
+        // push the sessions into sealed segments / flushed tables
+        for i in 0..300 {
+            engine.set_raw(format!("filler:{:04}", i), vec![0; 32]).unwrap();
+        }
+        assert_eq!(engine.get_raw("session:00").unwrap(), Some(vec![1; 32]));
+        assert_eq!(engine.scan_prefix("session:").unwrap().len(), 20);
+    }
+
+    std::thread::sleep(EXPIRED);
+
+    for engine in &engines {
+        assert_eq!(engine.get_raw("session:00").unwrap(), None);
+        assert!(engine.scan_prefix("session:").unwrap().is_empty());
+        assert_eq!(engine.get_raw("user:1").unwrap(), Some(vec![2; 32]));
+
+        // still stored until compaction drops them
+        assert_eq!(engine.storage.scan_prefix("session:").unwrap().len(), 20);
+        engine.compact().unwrap();
+        assert!(engine.storage.scan_prefix("session:").unwrap().is_empty());
+        assert_eq!(engine.scan_prefix("filler:").unwrap().len(), 300);
+    }
+}
+
+#[test]
+fn table_ttl_expires_rows() {
+    let root = temp_db("table");
+    let mut db = Enso::open_at(&root, "app").unwrap();
+    db.query("CREATE TABLE sessions (id INT PRIMARY KEY, token STRING) WITH TTL 2;").unwrap();
+    db.query("CREATE TABLE users (id INT PRIMARY KEY, name STRING);").unwrap();
+    assert_eq!(db.table_schema("sessions").unwrap().ttl, Some(2));
+    assert!(db.query("CREATE TABLE broken (id INT PRIMARY KEY) WITH TTL 0;").is_err());
+
+    db.insert_into("sessions", row![1, "abc"]).unwrap();
+    db.insert_into("users", row![1, "amartya"]).unwrap();
+    assert_eq!(db.select_all_from("sessions").unwrap().len(), 1);
+
+    // the ttl is part of the stored schema
+    drop(db);
+    let mut db = Enso::open_at(&root, "app").unwrap();
+    assert_eq!(db.table_schema("sessions").unwrap().ttl, Some(2));
+
+    std::thread::sleep(EXPIRED);
+    assert!(db.select_all_from("sessions").unwrap().is_empty());
+    assert!(db.select_by_pk_from("sessions", 1).unwrap().is_none());
+    assert_eq!(db.select_all_from("users").unwrap().len(), 1);
+}
+
+#[test]
+fn newer_keywords_still_name_tables_and_columns() {
+    let mut db = Enso::in_memory("test_db").unwrap();
+    db.query("CREATE TABLE s (id INT PRIMARY KEY, ttl INT);").unwrap();
+    db.query("CREATE TABLE backup (id INT PRIMARY KEY, with STRING, to STRING, begin INT, commit INT, rollback INT) with ttl 60;").unwrap();
+
+    db.query("INSERT INTO s VALUES (1, 30);").unwrap();
+    db.query("INSERT INTO backup VALUES (1, 'a', 'b', 2, 3, 4);").unwrap();
+    assert_eq!(db.select_all_from("s").unwrap().len(), 1);
+    assert_eq!(db.table_schema("backup").unwrap().ttl, Some(60));
+
+    db.query("begin;").unwrap();
+    db.query("DELETE FROM backup WHERE id = 1;").unwrap();
+    db.query("Commit;").unwrap();
+    assert!(db.select_all_from("backup").unwrap().is_empty());
+}
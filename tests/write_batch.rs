@@ -0,0 +1,95 @@
+mod common;
+
+use std::{fs::OpenOptions, time::Duration};
+
+use enso::{BackendKind, DbError, Engine, EngineConfig, WriteBatch};
+use common::{temp_db, config};
+
+#[test]
+fn batch_is_applied_as_a_whole_and_survives_a_reopen() {
+    for backend in [BackendKind::Log, BackendKind::Lsm] {
+        let path = temp_db(&format!("reopen-{:?}", backend));
+        let engine = Engine::open_with_config(&path, config(backend)).unwrap();
+        engine.set_raw("old".to_string(), vec![1]).unwrap();
+
+        // a bulk load bigger than a segment, still one entry
+        let mut batch = WriteBatch::new();
+        for i in 0..500 {
+            batch.put(format!("k{:04}", i), vec![2; 32]);
+        }
+        batch.delete("old".to_string());
+        batch.put("k0000".to_string(), vec![3]);
+        batch.put_with_ttl("session".to_string(), vec![4], Duration::from_secs(60));
+        assert_eq!(batch.len(), 503);
+
+        let seq = engine.snapshot().seq();
+        engine.write(batch).unwrap();
+        assert_eq!(engine.snapshot().seq(), seq + 503);
+        engine.write(WriteBatch::new()).unwrap();
+        assert_eq!(engine.snapshot().seq(), seq + 503);
+
+        let check = |engine: &Engine| {
+            assert_eq!(engine.scan_prefix("k").unwrap().len(), 500, "{:?}", backend);
+            assert_eq!(engine.get_raw("k0000").unwrap(), Some(vec![3]));
+            assert_eq!(engine.get_raw("old").unwrap(), None);
+            assert_eq!(engine.get_raw("session").unwrap(), Some(vec![4]));
+        };
+        check(&engine);
+        drop(engine);
+        check(&Engine::open_with_config(&path, config(backend)).unwrap());
+    }
+}
+
+#[test]
+fn torn_batch_is_absent_after_recovery() {
+    let path = temp_db("torn");
+    let engine = Engine::open_with_config(&path, config(BackendKind::Log).segment_size(1024 * 1024)).unwrap();
+    let mut batch = WriteBatch::new();
+    for i in 0..20 {
+        batch.put(format!("first:{:02}", i), vec![1; 32]);
+    }
+    engine.write(batch).unwrap();
+
+    let mut batch = WriteBatch::new();
+    for i in 0..20 {
+        batch.put(format!("second:{:02}", i), vec![2; 32]);
+    }
+    engine.write(batch).unwrap();
+    drop(engine);
+
+    // only the head of the second batch made it to disk
+    let segment = path.join("segments").join("enso-0001.log");
+    let file = OpenOptions::new().write(true).open(&segment).unwrap();
+    let len = file.metadata().unwrap().len();
+    file.set_len(len - 500).unwrap();
+    drop(file);
+
+    let engine = Engine::open_with_config(&path, config(BackendKind::Log)).unwrap();
+    assert!(engine.recovery_report().truncated_bytes > 0);
+    assert_eq!(engine.scan_prefix("first:").unwrap().len(), 20);
+    assert!(engine.scan_prefix("second:").unwrap().is_empty());
+}
+
+#[test]
+fn write_since_refuses_keys_written_after_the_snapshot() {
+    for backend in [BackendKind::Log, BackendKind::Lsm] {
+        let path = temp_db(&format!("since-{:?}", backend));
+        let engines = [Engine::open_with_config(&path, config(backend)).unwrap(), Engine::in_memory(EngineConfig::default())];
+        for engine in engines {
+            engine.set_raw("a".to_string(), vec![1]).unwrap();
+            let snapshot = engine.snapshot();
+            engine.delete_raw("a".to_string()).unwrap();
+
+            let mut batch = WriteBatch::new();
+            batch.put("a".to_string(), vec![2]);
+            batch.put("b".to_string(), vec![2]);
+            assert!(matches!(engine.write_since(batch, &snapshot), Err(DbError::TransactionConflict)), "{:?}", backend);
+            assert_eq!(engine.get_raw("b").unwrap(), None);
+
+            let mut batch = WriteBatch::new();
+            batch.put("b".to_string(), vec![2]);
+            engine.write_since(batch, &snapshot).unwrap();
+            assert_eq!(engine.get_raw("b").unwrap(), Some(vec![2]));
+        }
+    }
+}